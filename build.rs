@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+
+/**
+ Generates the `TokenKind` enum from the names in tokens.txt, so a token is
+ added to the language by adding one line to the spec. The lexer checks the
+ rest of each line when it compiles its DFA.
+*/
+fn main() {
+    println!("cargo:rerun-if-changed=tokens.txt");
+    let spec = fs::read_to_string("tokens.txt").expect("Error reading tokens.txt");
+
+    let mut variants = String::new();
+    let mut names: Vec<&str> = Vec::new();
+    for (number, line) in spec.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let name = line.split_whitespace().next().unwrap();
+        if names.contains(&name) {
+            panic!(
+                "tokens.txt line {}: token {name} is defined twice",
+                number + 1
+            );
+        }
+        if !name
+            .chars()
+            .all(|character| character.is_ascii_uppercase() || character == '_')
+        {
+            panic!(
+                "tokens.txt line {}: token name {name} must be UPPER_SNAKE_CASE",
+                number + 1
+            );
+        }
+        names.push(name);
+        variants.push_str(&format!("    {} => \"{name}\",\n", variant_name(name)));
+    }
+    if !names.contains(&"ERROR") {
+        variants.push_str("    Error => \"ERROR\",\n");
+    }
+    variants.push_str("    Eof => \"$\",\n");
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    fs::write(
+        Path::new(&out_dir).join("token_kinds.rs"),
+        format!("token_kinds! {{\n{variants}}}\n"),
+    )
+    .expect("Error writing token_kinds.rs");
+}
+
+/// LESS_THAN_OR_EQUAL -> LessThanOrEqual
+fn variant_name(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let lower = word.to_lowercase();
+            let mut chars = lower.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            format!("{first}{}", chars.as_str())
+        })
+        .collect()
+}
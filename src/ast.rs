@@ -0,0 +1,697 @@
+use crate::lexer::{self, Span, Token, TokenKind};
+use crate::parser::SyntaxTreeNode;
+use crate::semantic::SemanticError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Integer,
+    Float,
+    String,
+    Boolean,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "integer"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "boolean"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+}
+
+impl Literal {
+    pub fn ty(&self) -> Type {
+        match self {
+            Literal::Integer(_) => Type::Integer,
+            Literal::Float(_) => Type::Float,
+            Literal::String(_) => Type::String,
+            Literal::Boolean(_) => Type::Boolean,
+        }
+    }
+}
+
+/**
+ Binary operators. `And` and `Or` short-circuit: the right operand is only
+ evaluated when the left one does not decide the result.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulus,
+    Exponent,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulus => "%",
+            BinaryOp::Exponent => "^",
+            BinaryOp::Greater => ">",
+            BinaryOp::Less => "<",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Negate => write!(f, "-"),
+            UnaryOp::Not => write!(f, "!"),
+        }
+    }
+}
+
+/**
+ Variable name as written, with where it was written
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        span: Span,
+    },
+    Literal {
+        value: Literal,
+        span: Span,
+    },
+    Var(Ident),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary { span, .. } | Expr::Unary { span, .. } | Expr::Literal { span, .. } => {
+                *span
+            }
+            Expr::Var(ident) => ident.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Declare {
+        name: Ident,
+        ty: Type,
+        span: Span,
+    },
+    Assign {
+        name: Ident,
+        value: Expr,
+        span: Span,
+    },
+    /// `else_branch` is a Block, or an If for `else if`
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+        span: Span,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        span: Span,
+    },
+    Print {
+        value: Expr,
+        span: Span,
+    },
+    Block {
+        body: Vec<Stmt>,
+        span: Span,
+    },
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Declare { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Block { span, .. } => *span,
+        }
+    }
+}
+
+/**
+ Statements of `fn main() { ... }`, which form the outermost scope
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+/**
+ Lowers the parse tree into the AST. A complete parse is a single
+ `program-nt`; after error recovery the tree is a list of fragments and the
+ commands among them are lowered, skipping the ones that failed to parse.
+ Literals are checked here as they are converted: integers must fit in 64
+ bits and floats must be finite.
+*/
+pub fn lower(syntax_tree: &[SyntaxTreeNode]) -> Result<Program, SemanticError> {
+    let mut body = Vec::new();
+    for node in syntax_tree {
+        match node.symbol.as_str() {
+            "program-nt" => body.extend(lower_program(node)?),
+            "command-list-nt" => body.extend(lower_command_list(node)?),
+            "command-nt" => body.extend(lower_command(node)?),
+            // tokens and unfinished nonterminals of a broken command
+            _ => {}
+        }
+    }
+    let span = match (syntax_tree.first(), syntax_tree.last()) {
+        (Some(first), Some(last)) => first.span.merge(last.span),
+        _ => Span::default(),
+    };
+    Ok(Program { body, span })
+}
+
+/**
+ Symbols of a node's children, the right-hand side of the production it was
+ reduced by
+*/
+fn production(node: &SyntaxTreeNode) -> Vec<&str> {
+    node.children
+        .iter()
+        .map(|child| child.symbol.as_str())
+        .collect()
+}
+
+fn unexpected(node: &SyntaxTreeNode) -> SemanticError {
+    SemanticError {
+        message: format!(
+            "Cannot lower {} -> {}, the grammar and the AST are out of sync",
+            node.symbol,
+            production(node).join(" ")
+        ),
+        span: node.span,
+    }
+}
+
+fn token(node: &SyntaxTreeNode) -> Result<&Token, SemanticError> {
+    node.token.as_ref().ok_or_else(|| unexpected(node))
+}
+
+// program-nt ::= FN_PROGRAM MAIN_PROGRAM ( ) { command-list-nt }
+fn lower_program(node: &SyntaxTreeNode) -> Result<Vec<Stmt>, SemanticError> {
+    match production(node)[..] {
+        [_, _, _, _, _, "command-list-nt", _] => lower_command_list(&node.children[5]),
+        _ => Err(unexpected(node)),
+    }
+}
+
+// command-list-nt ::= command-nt ; command-list-nt | ε
+fn lower_command_list(node: &SyntaxTreeNode) -> Result<Vec<Stmt>, SemanticError> {
+    let mut body = Vec::new();
+    let mut list = node;
+    loop {
+        match production(list)[..] {
+            ["command-nt", "SEMICOLON", "command-list-nt"] => {
+                body.extend(lower_command(&list.children[0])?);
+                list = &list.children[2];
+            }
+            [] => return Ok(body),
+            _ => return Err(unexpected(list)),
+        }
+    }
+}
+
+// command-nt ::= assign-nt | declare-nt | print-nt | if-nt | while-nt, or the
+// `error` node left by recovery, which lowers to nothing
+fn lower_command(node: &SyntaxTreeNode) -> Result<Option<Stmt>, SemanticError> {
+    let command = match node.children.first() {
+        Some(command) if node.children.len() == 1 => command,
+        _ => return Err(unexpected(node)),
+    };
+    let stmt = match command.symbol.as_str() {
+        "assign-nt" => lower_assign(command)?,
+        "declare-nt" => lower_declare(command)?,
+        "print-nt" => lower_print(command)?,
+        "if-nt" => lower_if(command)?,
+        "while-nt" => lower_while(command)?,
+        "error" => return Ok(None),
+        _ => return Err(unexpected(node)),
+    };
+    Ok(Some(stmt))
+}
+
+// variable-nt ::= VARIABLE
+fn lower_variable(node: &SyntaxTreeNode) -> Result<Ident, SemanticError> {
+    match production(node)[..] {
+        ["VARIABLE"] => {
+            let token = token(&node.children[0])?;
+            Ok(Ident {
+                name: token.lexeme.clone(),
+                span: token.span,
+            })
+        }
+        _ => Err(unexpected(node)),
+    }
+}
+
+// assign-nt ::= variable-nt = exp-nt
+fn lower_assign(node: &SyntaxTreeNode) -> Result<Stmt, SemanticError> {
+    match production(node)[..] {
+        ["variable-nt", "EQUAL", "exp-nt"] => Ok(Stmt::Assign {
+            name: lower_variable(&node.children[0])?,
+            value: lower_exp(&node.children[2])?,
+            span: node.span,
+        }),
+        _ => Err(unexpected(node)),
+    }
+}
+
+// declare-nt ::= variable-nt : INTEGER_TYPE | STRING_TYPE | FLOAT_TYPE | BOOLEAN_TYPE
+fn lower_declare(node: &SyntaxTreeNode) -> Result<Stmt, SemanticError> {
+    let ty = match production(node)[..] {
+        ["variable-nt", "COLON", "INTEGER_TYPE"] => Type::Integer,
+        ["variable-nt", "COLON", "STRING_TYPE"] => Type::String,
+        ["variable-nt", "COLON", "FLOAT_TYPE"] => Type::Float,
+        ["variable-nt", "COLON", "BOOLEAN_TYPE"] => Type::Boolean,
+        _ => return Err(unexpected(node)),
+    };
+    Ok(Stmt::Declare {
+        name: lower_variable(&node.children[0])?,
+        ty,
+        span: node.span,
+    })
+}
+
+// print-nt ::= PRINT ( VARIABLE )
+fn lower_print(node: &SyntaxTreeNode) -> Result<Stmt, SemanticError> {
+    match production(node)[..] {
+        ["PRINT", "LEFT_PARENTHESIS", "VARIABLE", "RIGHT_PARENTHESIS"] => {
+            let token = token(&node.children[2])?;
+            Ok(Stmt::Print {
+                value: Expr::Var(Ident {
+                    name: token.lexeme.clone(),
+                    span: token.span,
+                }),
+                span: node.span,
+            })
+        }
+        _ => Err(unexpected(node)),
+    }
+}
+
+/**
+ Block of an if, else or while branch from its braces and command list
+*/
+fn lower_block(
+    open: &SyntaxTreeNode,
+    list: &SyntaxTreeNode,
+    close: &SyntaxTreeNode,
+) -> Result<Stmt, SemanticError> {
+    Ok(Stmt::Block {
+        body: lower_command_list(list)?,
+        span: open.span.merge(close.span),
+    })
+}
+
+// if-nt ::= IF ( exp-nt ) { command-list-nt } else-nt
+fn lower_if(node: &SyntaxTreeNode) -> Result<Stmt, SemanticError> {
+    match production(node)[..] {
+        ["IF", "LEFT_PARENTHESIS", "exp-nt", "RIGHT_PARENTHESIS", "LEFT_CURLY_BRACE", "command-list-nt", "RIGHT_CURLY_BRACE", "else-nt"] =>
+        {
+            let children = &node.children;
+            Ok(Stmt::If {
+                condition: lower_exp(&children[2])?,
+                then_branch: Box::new(lower_block(&children[4], &children[5], &children[6])?),
+                else_branch: lower_else(&children[7])?.map(Box::new),
+                span: node.span,
+            })
+        }
+        _ => Err(unexpected(node)),
+    }
+}
+
+// else-nt ::= ELSE { command-list-nt } | ELSE if-nt | ε
+fn lower_else(node: &SyntaxTreeNode) -> Result<Option<Stmt>, SemanticError> {
+    let children = &node.children;
+    match production(node)[..] {
+        ["ELSE", "LEFT_CURLY_BRACE", "command-list-nt", "RIGHT_CURLY_BRACE"] => {
+            Ok(Some(lower_block(&children[1], &children[2], &children[3])?))
+        }
+        ["ELSE", "if-nt"] => Ok(Some(lower_if(&children[1])?)),
+        [] => Ok(None),
+        _ => Err(unexpected(node)),
+    }
+}
+
+// while-nt ::= WHILE ( exp-nt ) { command-list-nt }
+fn lower_while(node: &SyntaxTreeNode) -> Result<Stmt, SemanticError> {
+    match production(node)[..] {
+        ["WHILE", "LEFT_PARENTHESIS", "exp-nt", "RIGHT_PARENTHESIS", "LEFT_CURLY_BRACE", "command-list-nt", "RIGHT_CURLY_BRACE"] =>
+        {
+            let children = &node.children;
+            Ok(Stmt::While {
+                condition: lower_exp(&children[2])?,
+                body: Box::new(lower_block(&children[4], &children[5], &children[6])?),
+                span: node.span,
+            })
+        }
+        _ => Err(unexpected(node)),
+    }
+}
+
+fn binary_op(kind: &str) -> Option<BinaryOp> {
+    let op = match kind {
+        "GREATER_THAN" => BinaryOp::Greater,
+        "LESS_THAN" => BinaryOp::Less,
+        "GREATER_THAN_OR_EQUAL" => BinaryOp::GreaterEqual,
+        "LESS_THAN_OR_EQUAL" => BinaryOp::LessEqual,
+        "EQUAL_EQUAL" => BinaryOp::Equal,
+        "NOT_EQUAL" => BinaryOp::NotEqual,
+        "AND" => BinaryOp::And,
+        "OR" => BinaryOp::Or,
+        "PLUS" => BinaryOp::Add,
+        "SUBTRACT" => BinaryOp::Subtract,
+        "MULTIPLY" => BinaryOp::Multiply,
+        "DIVIDE" => BinaryOp::Divide,
+        "MODULUS" => BinaryOp::Modulus,
+        "EXPONENT" => BinaryOp::Exponent,
+        _ => return None,
+    };
+    Some(op)
+}
+
+// exp-nt ::= exp-nt op exp-nt | SUBTRACT exp-nt | NOT exp-nt | ( exp-nt )
+//          | variable-nt | NUMBER | FLOAT | STRING | TRUE | FALSE
+// the parse table already applied precedence and associativity, so the tree
+// is lowered as it is
+fn lower_exp(node: &SyntaxTreeNode) -> Result<Expr, SemanticError> {
+    match production(node)[..] {
+        ["exp-nt", op, "exp-nt"] => {
+            let op = binary_op(op).ok_or_else(|| unexpected(node))?;
+            Ok(Expr::Binary {
+                op,
+                left: Box::new(lower_exp(&node.children[0])?),
+                right: Box::new(lower_exp(&node.children[2])?),
+                span: node.span,
+            })
+        }
+        ["LEFT_PARENTHESIS", "exp-nt", "RIGHT_PARENTHESIS"] => lower_exp(&node.children[1]),
+        ["variable-nt"] => Ok(Expr::Var(lower_variable(&node.children[0])?)),
+        ["NUMBER"] | ["FLOAT"] | ["STRING"] | ["TRUE"] | ["FALSE"] => {
+            let token = token(&node.children[0])?;
+            Ok(Expr::Literal {
+                value: lower_literal(token, false)?,
+                span: token.span,
+            })
+        }
+        ["NOT", "exp-nt"] => Ok(Expr::Unary {
+            op: UnaryOp::Not,
+            operand: Box::new(lower_exp(&node.children[1])?),
+            span: node.span,
+        }),
+        ["SUBTRACT", "exp-nt"] => {
+            let operand = &node.children[1];
+            // a minus right before a number is a negative literal, which lets
+            // the most negative integer be written
+            if let ["NUMBER"] | ["FLOAT"] = production(operand)[..] {
+                return Ok(Expr::Literal {
+                    value: lower_literal(token(&operand.children[0])?, true)?,
+                    span: node.span,
+                });
+            }
+            Ok(Expr::Unary {
+                op: UnaryOp::Negate,
+                operand: Box::new(lower_exp(operand)?),
+                span: node.span,
+            })
+        }
+        _ => Err(unexpected(node)),
+    }
+}
+
+fn lower_literal(token: &Token, negated: bool) -> Result<Literal, SemanticError> {
+    match token.value {
+        TokenKind::Number => {
+            let limit = if negated {
+                i64::MAX as u64 + 1
+            } else {
+                i64::MAX as u64
+            };
+            match lexer::integer_literal(&token.lexeme) {
+                Some(value) if value <= limit => Ok(Literal::Integer(if negated {
+                    (value as i64).wrapping_neg()
+                } else {
+                    value as i64
+                })),
+                _ => Err(SemanticError {
+                    message: format!(
+                        "Integer literal {} does not fit in a 64-bit integer",
+                        token.lexeme
+                    ),
+                    span: token.span,
+                }),
+            }
+        }
+        TokenKind::Float => match lexer::float_literal(&token.lexeme) {
+            Some(value) if value.is_finite() => {
+                Ok(Literal::Float(if negated { -value } else { value }))
+            }
+            _ => Err(SemanticError {
+                message: format!("Float literal {} is out of range", token.lexeme),
+                span: token.span,
+            }),
+        },
+        TokenKind::True => Ok(Literal::Boolean(true)),
+        TokenKind::False => Ok(Literal::Boolean(false)),
+        TokenKind::String => Ok(Literal::String(token.literal.clone().unwrap_or_default())),
+        _ => Err(SemanticError {
+            message: format!("{} is not a literal", token.value),
+            span: token.span,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::lowered;
+
+    fn parse_expr(source: &str) -> Result<Expr, Vec<String>> {
+        let program = lowered(&format!("fn main() {{ x = {source}; }}"))?;
+        match program.body.into_iter().next() {
+            Some(Stmt::Assign { value, .. }) => Ok(value),
+            other => panic!("expected an assignment, got {other:?}"),
+        }
+    }
+
+    /// integer evaluation, comparisons give 1 or 0
+    fn eval(expr: &Expr) -> i64 {
+        match expr {
+            Expr::Literal {
+                value: Literal::Integer(value),
+                ..
+            } => *value,
+            Expr::Literal {
+                value: Literal::Boolean(value),
+                ..
+            } => *value as i64,
+            Expr::Unary {
+                op: UnaryOp::Negate,
+                operand,
+                ..
+            } => -eval(operand),
+            Expr::Unary {
+                op: UnaryOp::Not,
+                operand,
+                ..
+            } => (eval(operand) == 0) as i64,
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                let (left, right) = (eval(left), eval(right));
+                match op {
+                    BinaryOp::Add => left + right,
+                    BinaryOp::Subtract => left - right,
+                    BinaryOp::Multiply => left * right,
+                    BinaryOp::Divide => left / right,
+                    BinaryOp::Modulus => left % right,
+                    BinaryOp::Exponent => left.pow(right as u32),
+                    BinaryOp::Greater => (left > right) as i64,
+                    BinaryOp::Less => (left < right) as i64,
+                    BinaryOp::GreaterEqual => (left >= right) as i64,
+                    BinaryOp::LessEqual => (left <= right) as i64,
+                    BinaryOp::Equal => (left == right) as i64,
+                    BinaryOp::NotEqual => (left != right) as i64,
+                    BinaryOp::And => (left != 0 && right != 0) as i64,
+                    BinaryOp::Or => (left != 0 || right != 0) as i64,
+                }
+            }
+            other => panic!("cannot evaluate {other:?}"),
+        }
+    }
+
+    /// fully parenthesized form of the tree
+    fn render(expr: &Expr) -> String {
+        match expr {
+            Expr::Literal {
+                value: Literal::Integer(value),
+                ..
+            } => value.to_string(),
+            Expr::Literal {
+                value: Literal::Boolean(value),
+                ..
+            } => value.to_string(),
+            Expr::Var(ident) => ident.name.clone(),
+            Expr::Unary { op, operand, .. } => format!("({op}{})", render(operand)),
+            Expr::Binary {
+                op, left, right, ..
+            } => format!("({} {op} {})", render(left), render(right)),
+            other => panic!("cannot render {other:?}"),
+        }
+    }
+
+    fn assert_evaluates(source: &str, expected: i64) {
+        let expr = parse_expr(source).unwrap();
+        assert_eq!(
+            eval(&expr),
+            expected,
+            "{source} parsed as {}",
+            render(&expr)
+        );
+    }
+
+    #[test]
+    fn multiplicative_operators_bind_tighter_than_additive() {
+        assert_evaluates("2 + 3 * 4", 14);
+        assert_evaluates("2 * 3 + 4", 10);
+        assert_evaluates("20 - 12 / 4", 17);
+        assert_evaluates("1 + 17 % 5", 3);
+    }
+
+    #[test]
+    fn same_level_operators_are_left_associative() {
+        assert_evaluates("10 - 4 - 3", 3);
+        assert_evaluates("100 / 10 / 5", 2);
+        assert_evaluates("17 % 5 * 2", 4);
+        assert_eq!(render(&parse_expr("a - b + c").unwrap()), "((a - b) + c)");
+    }
+
+    #[test]
+    fn exponent_is_right_associative_and_binds_tightest() {
+        assert_evaluates("2 ^ 3 ^ 2", 512);
+        assert_evaluates("2 * 3 ^ 2", 18);
+        assert_evaluates("-2 ^ 2", -4);
+        assert_eq!(render(&parse_expr("a ^ b ^ c").unwrap()), "(a ^ (b ^ c))");
+    }
+
+    #[test]
+    fn unary_minus_binds_tighter_than_multiplication() {
+        assert_evaluates("-2 * 3 + 10", 4);
+        assert_evaluates("2 - -3", 5);
+        assert_eq!(render(&parse_expr("-a * b").unwrap()), "((-a) * b)");
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_evaluates("(2 + 3) * 4", 20);
+        assert_evaluates("(2 ^ 3) ^ 2", 64);
+        assert_evaluates("2 * (10 - (4 - 3))", 18);
+    }
+
+    #[test]
+    fn comparisons_bind_loosest_and_do_not_chain() {
+        assert_evaluates("1 + 2 < 2 * 2", 1);
+        assert_evaluates("2 * 3 == 3 + 3", 1);
+        assert_evaluates("2 ^ 3 >= 3 ^ 2", 0);
+        assert!(parse_expr("1 < 2 < 3").is_err());
+    }
+
+    #[test]
+    fn logical_operators_bind_looser_than_comparisons() {
+        assert_eq!(
+            render(&parse_expr("a || b && c").unwrap()),
+            "(a || (b && c))"
+        );
+        assert_eq!(
+            render(&parse_expr("a < b && c != d || !e").unwrap()),
+            "(((a < b) && (c != d)) || (!e))"
+        );
+        assert_evaluates("1 + 1 == 2 && !(3 < 2) || false", 1);
+        assert_evaluates("true && false || 2 != 2", 0);
+        assert_evaluates("!true == false", 1);
+    }
+
+    #[test]
+    fn else_if_chains_nest_to_the_right() {
+        let program =
+            lowered("fn main() { if (a) { x = 1; } else if (b) { x = 2; } else { x = 3; }; }")
+                .unwrap();
+        let Some(Stmt::If {
+            else_branch: Some(else_if),
+            ..
+        }) = program.body.first()
+        else {
+            panic!("expected an if with an else, got {:?}", program.body);
+        };
+        let Stmt::If {
+            condition,
+            else_branch: Some(last),
+            ..
+        } = else_if.as_ref()
+        else {
+            panic!("expected else if, got {else_if:?}");
+        };
+        assert_eq!(render(condition), "b");
+        assert!(matches!(last.as_ref(), Stmt::Block { .. }));
+    }
+}
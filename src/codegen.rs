@@ -0,0 +1,412 @@
+use crate::ast::{BinaryOp, Expr, Ident, Program, Stmt, UnaryOp};
+use crate::interpreter::Value;
+use crate::lexer::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+/**
+ Instructions of the stack VM. Operators pop their operands and push their
+ result, jumps hold the index of the instruction they go to.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// pushes a value of the constant pool
+    Const(usize),
+    /// pushes the value of a variable slot
+    Load(usize),
+    /// pops into a variable slot
+    Store(usize),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulus,
+    Exponent,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Equal,
+    NotEqual,
+    Negate,
+    Not,
+    Jump(usize),
+    /// pops a boolean and jumps when it is false
+    JumpIfFalse(usize),
+    /// pops a boolean and jumps when it is true
+    JumpIfTrue(usize),
+    /// pops a value and prints it on its own line
+    Print,
+    /// empties a variable slot when the block declaring it ends
+    Free(usize),
+}
+
+impl Instruction {
+    fn binary(op: BinaryOp) -> Instruction {
+        match op {
+            BinaryOp::Add => Instruction::Add,
+            BinaryOp::Subtract => Instruction::Subtract,
+            BinaryOp::Multiply => Instruction::Multiply,
+            BinaryOp::Divide => Instruction::Divide,
+            BinaryOp::Modulus => Instruction::Modulus,
+            BinaryOp::Exponent => Instruction::Exponent,
+            BinaryOp::Greater => Instruction::Greater,
+            BinaryOp::Less => Instruction::Less,
+            BinaryOp::GreaterEqual => Instruction::GreaterEqual,
+            BinaryOp::LessEqual => Instruction::LessEqual,
+            BinaryOp::Equal => Instruction::Equal,
+            BinaryOp::NotEqual => Instruction::NotEqual,
+            BinaryOp::And | BinaryOp::Or => unreachable!("{op} is compiled into jumps"),
+        }
+    }
+
+    /**
+     Operator applied by an arithmetic or comparison instruction
+    */
+    pub fn binary_op(self) -> Option<BinaryOp> {
+        match self {
+            Instruction::Add => Some(BinaryOp::Add),
+            Instruction::Subtract => Some(BinaryOp::Subtract),
+            Instruction::Multiply => Some(BinaryOp::Multiply),
+            Instruction::Divide => Some(BinaryOp::Divide),
+            Instruction::Modulus => Some(BinaryOp::Modulus),
+            Instruction::Exponent => Some(BinaryOp::Exponent),
+            Instruction::Greater => Some(BinaryOp::Greater),
+            Instruction::Less => Some(BinaryOp::Less),
+            Instruction::GreaterEqual => Some(BinaryOp::GreaterEqual),
+            Instruction::LessEqual => Some(BinaryOp::LessEqual),
+            Instruction::Equal => Some(BinaryOp::Equal),
+            Instruction::NotEqual => Some(BinaryOp::NotEqual),
+            _ => None,
+        }
+    }
+
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Instruction::Const(_) => "CONST",
+            Instruction::Load(_) => "LOAD",
+            Instruction::Store(_) => "STORE",
+            Instruction::Add => "ADD",
+            Instruction::Subtract => "SUB",
+            Instruction::Multiply => "MUL",
+            Instruction::Divide => "DIV",
+            Instruction::Modulus => "MOD",
+            Instruction::Exponent => "POW",
+            Instruction::Greater => "GT",
+            Instruction::Less => "LT",
+            Instruction::GreaterEqual => "GE",
+            Instruction::LessEqual => "LE",
+            Instruction::Equal => "EQ",
+            Instruction::NotEqual => "NE",
+            Instruction::Negate => "NEG",
+            Instruction::Not => "NOT",
+            Instruction::Jump(_) => "JMP",
+            Instruction::JumpIfFalse(_) => "JMP_IF_FALSE",
+            Instruction::JumpIfTrue(_) => "JMP_IF_TRUE",
+            Instruction::Print => "PRINT",
+            Instruction::Free(_) => "FREE",
+        }
+    }
+
+    fn operand(self) -> Option<usize> {
+        match self {
+            Instruction::Const(operand)
+            | Instruction::Load(operand)
+            | Instruction::Store(operand)
+            | Instruction::Jump(operand)
+            | Instruction::JumpIfFalse(operand)
+            | Instruction::JumpIfTrue(operand)
+            | Instruction::Free(operand) => Some(operand),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operand() {
+            Some(operand) => write!(f, "{} {operand}", self.mnemonic()),
+            None => write!(f, "{}", self.mnemonic()),
+        }
+    }
+}
+
+/**
+ Compiled program. Every variable declaration gets its own slot, so scoping
+ is resolved here and the VM only sees slot numbers. `spans` has the source
+ position of each instruction, for runtime errors and the listing.
+ `steps` has, for each instruction, the statements and expressions the tree
+ interpreter starts at that point of the program: the VM counts them before
+ running the instruction, so both backends count the same steps in the same
+ order and stop at the same place.
+*/
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub spans: Vec<Span>,
+    pub steps: Vec<Vec<Span>>,
+    pub constants: Vec<Value>,
+    pub slots: Vec<String>,
+}
+
+struct Codegen {
+    chunk: Chunk,
+    /// slots of the variables declared in each enclosing block
+    scopes: Vec<HashMap<String, usize>>,
+    /// nodes started since the last instruction, counted by the next one
+    pending_steps: Vec<Span>,
+}
+
+impl Codegen {
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.chunk.code.push(instruction);
+        self.chunk.spans.push(span);
+        self.chunk
+            .steps
+            .push(std::mem::take(&mut self.pending_steps));
+        self.chunk.code.len() - 1
+    }
+
+    /**
+     Index of the next instruction, to be jumped to. Steps still pending, like
+     the one of an empty block, get a jump to the next instruction of their
+     own so they are not counted on the other paths reaching the label.
+    */
+    fn label(&mut self) -> usize {
+        if let Some(&span) = self.pending_steps.last() {
+            let next = self.chunk.code.len() + 1;
+            self.emit(Instruction::Jump(next), span);
+        }
+        self.chunk.code.len()
+    }
+
+    /**
+     Points the jump at `at` to the next instruction to be emitted
+    */
+    fn patch(&mut self, at: usize) {
+        let target = self.label();
+        self.chunk.code[at] = match self.chunk.code[at] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            Instruction::JumpIfTrue(_) => Instruction::JumpIfTrue(target),
+            instruction => unreachable!("{instruction} is not a jump"),
+        };
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        // floats by their bits so 0.0 and -0.0 stay apart
+        let same = |known: &Value| match (known, &value) {
+            (Value::Float(known), Value::Float(value)) => known.to_bits() == value.to_bits(),
+            (known, value) => known == value,
+        };
+        if let Some(index) = self.chunk.constants.iter().position(same) {
+            return index;
+        }
+        self.chunk.constants.push(value);
+        self.chunk.constants.len() - 1
+    }
+
+    fn slot(&self, name: &Ident) -> usize {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.name))
+            .copied()
+            .expect("variables are checked by the semantic analysis")
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.pending_steps.push(expr.span());
+        match expr {
+            Expr::Literal { value, span } => {
+                let index = self.constant(Value::from(value));
+                self.emit(Instruction::Const(index), *span);
+            }
+            Expr::Var(name) => {
+                let slot = self.slot(name);
+                self.emit(Instruction::Load(slot), name.span);
+            }
+            Expr::Unary { op, operand, span } => {
+                self.expr(operand);
+                let instruction = match op {
+                    UnaryOp::Negate => Instruction::Negate,
+                    UnaryOp::Not => Instruction::Not,
+                };
+                self.emit(instruction, *span);
+            }
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+                span,
+            } => {
+                // left ? right : false
+                self.expr(left);
+                let to_false = self.emit(Instruction::JumpIfFalse(0), *span);
+                self.expr(right);
+                let to_end = self.emit(Instruction::Jump(0), *span);
+                self.patch(to_false);
+                let index = self.constant(Value::Boolean(false));
+                self.emit(Instruction::Const(index), *span);
+                self.patch(to_end);
+            }
+            Expr::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+                span,
+            } => {
+                // left ? true : right
+                self.expr(left);
+                let to_right = self.emit(Instruction::JumpIfFalse(0), *span);
+                let index = self.constant(Value::Boolean(true));
+                self.emit(Instruction::Const(index), *span);
+                let to_end = self.emit(Instruction::Jump(0), *span);
+                self.patch(to_right);
+                self.expr(right);
+                self.patch(to_end);
+            }
+            Expr::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Instruction::binary(*op), *span);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.pending_steps.push(stmt.span());
+        match stmt {
+            Stmt::Declare { name, ty, span } => {
+                // a declaration runs again on every pass of a loop, resetting the variable
+                let slot = self.chunk.slots.len();
+                self.chunk.slots.push(name.name.clone());
+                let scope = self.scopes.last_mut().expect("expects a scope");
+                scope.insert(name.name.clone(), slot);
+                let index = self.constant(Value::default_of(*ty));
+                self.emit(Instruction::Const(index), *span);
+                // stores report errors at the variable, like the interpreter
+                self.emit(Instruction::Store(slot), name.span);
+            }
+            Stmt::Assign { name, value, .. } => {
+                self.expr(value);
+                let slot = self.slot(name);
+                self.emit(Instruction::Store(slot), name.span);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                span,
+            } => {
+                self.expr(condition);
+                let to_else = self.emit(Instruction::JumpIfFalse(0), *span);
+                self.stmt(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let to_end = self.emit(Instruction::Jump(0), *span);
+                        self.patch(to_else);
+                        self.stmt(else_branch);
+                        self.patch(to_end);
+                    }
+                    None => self.patch(to_else),
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                span,
+            } => {
+                // the condition goes after the body, the loop takes one jump per pass
+                let to_condition = self.emit(Instruction::Jump(0), *span);
+                let start = self.label();
+                self.stmt(body);
+                self.patch(to_condition);
+                self.expr(condition);
+                self.emit(Instruction::JumpIfTrue(start), *span);
+            }
+            Stmt::Print { value, span } => {
+                self.expr(value);
+                self.emit(Instruction::Print, *span);
+            }
+            Stmt::Block { body, span } => {
+                self.scopes.push(HashMap::new());
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                // give back the memory of the block's variables, as the interpreter does
+                let scope = self.scopes.pop().expect("expects a scope");
+                let mut slots: Vec<usize> = scope.into_values().collect();
+                slots.sort();
+                for slot in slots {
+                    self.emit(Instruction::Free(slot), *span);
+                }
+            }
+        }
+    }
+}
+
+/**
+ Compiles a program that passed semantic analysis into bytecode
+*/
+pub fn compile(program: &Program) -> Chunk {
+    let mut codegen = Codegen {
+        chunk: Chunk::default(),
+        scopes: vec![HashMap::new()],
+        pending_steps: Vec::new(),
+    };
+    for stmt in &program.body {
+        codegen.stmt(stmt);
+    }
+    codegen.label();
+    codegen.chunk
+}
+
+/**
+ Listing of a chunk: its constant pool, its slots, then one instruction per
+ line with its index, the source line it came from (`|` when it is the same
+ as the previous one) and what its operand refers to
+*/
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut listing = String::from("== constants ==\n");
+    for (index, value) in chunk.constants.iter().enumerate() {
+        listing.push_str(&format!("{index:>4}  {}\n", value.literal()));
+    }
+    listing.push_str("== slots ==\n");
+    for (index, name) in chunk.slots.iter().enumerate() {
+        listing.push_str(&format!("{index:>4}  {name}\n"));
+    }
+    listing.push_str("== code ==\n");
+    let mut previous_line = None;
+    for (index, (instruction, span)) in chunk.code.iter().zip(&chunk.spans).enumerate() {
+        let line = if previous_line == Some(span.start_line) {
+            "|".to_string()
+        } else {
+            span.start_line.to_string()
+        };
+        previous_line = Some(span.start_line);
+        let operand = instruction
+            .operand()
+            .map(|operand| operand.to_string())
+            .unwrap_or_default();
+        let comment = match instruction {
+            Instruction::Const(index) => format!("  ; {}", chunk.constants[*index].literal()),
+            Instruction::Load(slot) | Instruction::Store(slot) | Instruction::Free(slot) => {
+                format!("  ; {}", chunk.slots[*slot])
+            }
+            _ => String::new(),
+        };
+        let text = format!(
+            "{index:04}  {line:>4}  {:<13}{operand:>4}{comment}",
+            instruction.mnemonic()
+        );
+        listing.push_str(text.trim_end());
+        listing.push('\n');
+    }
+    listing
+}
@@ -0,0 +1,469 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+
+/**
+ Set of bytes a single transition accepts
+*/
+#[derive(Clone)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn empty() -> ByteSet {
+        ByteSet([0; 4])
+    }
+
+    fn single(byte: u8) -> ByteSet {
+        let mut set = ByteSet::empty();
+        set.range(byte, byte);
+        set
+    }
+
+    fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+
+    fn range(&mut self, from: u8, to: u8) {
+        for byte in from..=to {
+            self.0[byte as usize / 64] |= 1 << (byte % 64);
+        }
+    }
+
+    fn union(&mut self, other: &ByteSet) {
+        for (word, other) in self.0.iter_mut().zip(other.0) {
+            *word |= other;
+        }
+    }
+
+    fn negate(&mut self) {
+        for word in self.0.iter_mut() {
+            *word = !*word;
+        }
+    }
+}
+
+/**
+ Parsed pattern, the subset of regular expressions token rules are written in
+*/
+enum Regex {
+    Empty,
+    Bytes(ByteSet),
+    Concat(Vec<Regex>),
+    Alternation(Vec<Regex>),
+    Star(Box<Regex>),
+    Plus(Box<Regex>),
+    Optional(Box<Regex>),
+}
+
+/**
+ Recursive descent parser for patterns: literals, `.`, classes like `[a-z_]`
+ and `[^"\n]`, the escapes `\d \w \s \n \t \r` (any other escaped character is
+ literal), grouping, `|`, `*`, `+` and `?`. Matching is on bytes, a non-ASCII
+ literal matches its UTF-8 encoding.
+*/
+struct PatternParser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl<'a> PatternParser<'a> {
+    fn parse(pattern: &'a str) -> Result<Regex, String> {
+        let mut parser = PatternParser {
+            pattern,
+            chars: pattern.chars().collect(),
+            position: 0,
+        };
+        let regex = parser.alternation()?;
+        match parser.peek() {
+            None => Ok(regex),
+            Some(character) => Err(parser.error(&format!("unexpected '{character}'"))),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!(
+            "{message} at position {} of pattern '{}'",
+            self.position, self.pattern
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let character = self.peek()?;
+        self.position += 1;
+        Some(character)
+    }
+
+    fn alternation(&mut self) -> Result<Regex, String> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.position += 1;
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Regex::Alternation(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Regex, String> {
+        let mut items = Vec::new();
+        while let Some(character) = self.peek() {
+            if character == '|' || character == ')' {
+                break;
+            }
+            let mut item = self.atom()?;
+            loop {
+                item = match self.peek() {
+                    Some('*') => Regex::Star(Box::new(item)),
+                    Some('+') => Regex::Plus(Box::new(item)),
+                    Some('?') => Regex::Optional(Box::new(item)),
+                    _ => break,
+                };
+                self.position += 1;
+            }
+            items.push(item);
+        }
+        Ok(match items.len() {
+            0 => Regex::Empty,
+            1 => items.pop().unwrap(),
+            _ => Regex::Concat(items),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Regex, String> {
+        match self.next() {
+            Some('(') => {
+                let inner = self.alternation()?;
+                if self.next() != Some(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                Ok(inner)
+            }
+            Some('[') => self.class(),
+            Some('.') => {
+                let mut set = ByteSet::single(b'\n');
+                set.negate();
+                Ok(Regex::Bytes(set))
+            }
+            Some('\\') => Ok(Regex::Bytes(self.escape()?)),
+            Some(character @ ('*' | '+' | '?')) => {
+                Err(self.error(&format!("'{character}' has nothing to repeat")))
+            }
+            Some(character) => Ok(literal(character)),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn escape(&mut self) -> Result<ByteSet, String> {
+        let mut set = ByteSet::empty();
+        match self.next() {
+            Some('d') => set.range(b'0', b'9'),
+            Some('w') => {
+                set.range(b'a', b'z');
+                set.range(b'A', b'Z');
+                set.range(b'0', b'9');
+                set.range(b'_', b'_');
+            }
+            Some('s') => {
+                for byte in [b' ', b'\t', b'\n', b'\r', 0x0b, 0x0c] {
+                    set.range(byte, byte);
+                }
+            }
+            Some('n') => set.range(b'\n', b'\n'),
+            Some('t') => set.range(b'\t', b'\t'),
+            Some('r') => set.range(b'\r', b'\r'),
+            Some(character) if character.is_ascii() => set.range(character as u8, character as u8),
+            Some(character) => return Err(self.error(&format!("cannot escape '{character}'"))),
+            None => return Err(self.error("unfinished escape")),
+        }
+        Ok(set)
+    }
+
+    fn class(&mut self) -> Result<Regex, String> {
+        let mut set = ByteSet::empty();
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        loop {
+            let from = match self.next() {
+                Some(']') => break,
+                Some('\\') => {
+                    set.union(&self.escape()?);
+                    continue;
+                }
+                Some(character) if character.is_ascii() => character as u8,
+                Some(character) => {
+                    return Err(self.error(&format!("non-ASCII '{character}' in a class")))
+                }
+                None => return Err(self.error("missing ']'")),
+            };
+            if self.peek() == Some('-') && self.chars.get(self.position + 1) != Some(&']') {
+                self.position += 1;
+                match self.next() {
+                    Some(to) if to.is_ascii() && to as u8 >= from => set.range(from, to as u8),
+                    _ => return Err(self.error("invalid class range")),
+                }
+            } else {
+                set.range(from, from);
+            }
+        }
+        if negated {
+            set.negate();
+        }
+        Ok(Regex::Bytes(set))
+    }
+}
+
+fn literal(character: char) -> Regex {
+    let mut buffer = [0; 4];
+    let bytes: Vec<Regex> = character
+        .encode_utf8(&mut buffer)
+        .bytes()
+        .map(|byte| Regex::Bytes(ByteSet::single(byte)))
+        .collect();
+    if bytes.len() == 1 {
+        bytes.into_iter().next().unwrap()
+    } else {
+        Regex::Concat(bytes)
+    }
+}
+
+/**
+ Thompson NFA state: an optional byte transition plus epsilon moves
+*/
+#[derive(Default)]
+struct NfaState {
+    on: Option<(ByteSet, usize)>,
+    epsilon: Vec<usize>,
+    accept: Option<(i32, usize)>,
+}
+
+#[derive(Default)]
+struct Nfa {
+    states: Vec<NfaState>,
+}
+
+impl Nfa {
+    fn add_state(&mut self) -> usize {
+        self.states.push(NfaState::default());
+        self.states.len() - 1
+    }
+
+    /**
+     Adds the states of a regex, returning its (start, end) pair
+    */
+    fn fragment(&mut self, regex: &Regex) -> (usize, usize) {
+        match regex {
+            Regex::Empty => {
+                let state = self.add_state();
+                (state, state)
+            }
+            Regex::Bytes(set) => {
+                let start = self.add_state();
+                let end = self.add_state();
+                self.states[start].on = Some((set.clone(), end));
+                (start, end)
+            }
+            Regex::Concat(items) => {
+                let (start, mut end) = self.fragment(&items[0]);
+                for item in &items[1..] {
+                    let (item_start, item_end) = self.fragment(item);
+                    self.states[end].epsilon.push(item_start);
+                    end = item_end;
+                }
+                (start, end)
+            }
+            Regex::Alternation(branches) => {
+                let start = self.add_state();
+                let end = self.add_state();
+                for branch in branches {
+                    let (branch_start, branch_end) = self.fragment(branch);
+                    self.states[start].epsilon.push(branch_start);
+                    self.states[branch_end].epsilon.push(end);
+                }
+                (start, end)
+            }
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => {
+                let start = self.add_state();
+                let end = self.add_state();
+                let (inner_start, inner_end) = self.fragment(inner);
+                self.states[start].epsilon.push(inner_start);
+                self.states[inner_end].epsilon.push(end);
+                if !matches!(regex, Regex::Plus(_)) {
+                    self.states[start].epsilon.push(end);
+                }
+                if !matches!(regex, Regex::Optional(_)) {
+                    self.states[inner_end].epsilon.push(inner_start);
+                }
+                (start, end)
+            }
+        }
+    }
+
+    fn closure(&self, states: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut pending: Vec<usize> = states.into_iter().collect();
+        while let Some(state) = pending.pop() {
+            if closure.insert(state) {
+                pending.extend(&self.states[state].epsilon);
+            }
+        }
+        closure
+    }
+}
+
+pub type DfaState = u32;
+
+/// state every failed transition leads to
+pub const DEAD: DfaState = 0;
+const START: DfaState = 1;
+
+/**
+ Deterministic automaton recognizing a list of rules at once. Each row maps
+ every byte to the next state; `accepts` holds the rule a state accepts,
+ the one with the highest priority (then the one listed first) when several
+ rules match the same text.
+*/
+pub struct Dfa {
+    transitions: Vec<[DfaState; 256]>,
+    accepts: Vec<Option<usize>>,
+}
+
+impl Dfa {
+    /**
+     Compiles `(pattern, priority)` rules into one DFA by subset construction,
+     rules are numbered in order
+    */
+    pub fn new(rules: &[(&str, i32)]) -> Result<Dfa, String> {
+        let mut nfa = Nfa::default();
+        let start = nfa.add_state();
+        for (rule, (pattern, priority)) in rules.iter().enumerate() {
+            let regex = PatternParser::parse(pattern)?;
+            let (rule_start, rule_end) = nfa.fragment(&regex);
+            nfa.states[start].epsilon.push(rule_start);
+            nfa.states[rule_end].accept = Some((*priority, rule));
+        }
+
+        let mut dfa = Dfa {
+            transitions: vec![[DEAD; 256]],
+            accepts: vec![None],
+        };
+        let mut ids: HashMap<BTreeSet<usize>, DfaState> = HashMap::new();
+        let mut pending: Vec<BTreeSet<usize>> = Vec::new();
+
+        let initial = nfa.closure([start]);
+        ids.insert(initial.clone(), START);
+        dfa.add_state(&nfa, &initial);
+        pending.push(initial);
+
+        while let Some(set) = pending.pop() {
+            let from = ids[&set];
+            for byte in 0..256 {
+                let targets = set.iter().filter_map(|state| match &nfa.states[*state].on {
+                    Some((bytes, target)) if bytes.contains(byte as u8) => Some(*target),
+                    _ => None,
+                });
+                let target_set = nfa.closure(targets);
+                if target_set.is_empty() {
+                    continue;
+                }
+                let target = match ids.get(&target_set) {
+                    Some(target) => *target,
+                    None => {
+                        let target = dfa.add_state(&nfa, &target_set);
+                        ids.insert(target_set.clone(), target);
+                        pending.push(target_set);
+                        target
+                    }
+                };
+                dfa.transitions[from as usize][byte] = target;
+            }
+        }
+        Ok(dfa)
+    }
+
+    fn add_state(&mut self, nfa: &Nfa, set: &BTreeSet<usize>) -> DfaState {
+        self.transitions.push([DEAD; 256]);
+        let accept = set
+            .iter()
+            .filter_map(|state| nfa.states[*state].accept)
+            .max_by_key(|(priority, rule)| (*priority, Reverse(*rule)));
+        self.accepts.push(accept.map(|(_, rule)| rule));
+        (self.transitions.len() - 1) as DfaState
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /**
+     Maximal munch: runs the automaton from `start` until it dies and returns
+     the rule and end offset of the longest accepted prefix
+    */
+    pub fn longest_match(&self, input: &[u8], start: usize) -> Option<(usize, usize)> {
+        let mut state = START;
+        let mut last_accept = None;
+        for (offset, byte) in input[start..].iter().enumerate() {
+            state = self.transitions[state as usize][*byte as usize];
+            if state == DEAD {
+                break;
+            }
+            if let Some(rule) = self.accepts[state as usize] {
+                last_accept = Some((rule, start + offset + 1));
+            }
+        }
+        last_accept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(dfa: &Dfa, input: &str) -> Option<(usize, usize)> {
+        dfa.longest_match(input.as_bytes(), 0)
+    }
+
+    #[test]
+    fn longest_match_wins_and_backs_up_to_the_last_accept() {
+        let dfa = Dfa::new(&[("<", 1), ("<=", 1), ("abc", 1), ("a", 1)]).unwrap();
+        assert_eq!(matches(&dfa, "<=1"), Some((1, 2)));
+        assert_eq!(matches(&dfa, "< 1"), Some((0, 1)));
+        assert_eq!(matches(&dfa, "abc"), Some((2, 3)));
+        // `ab` is a prefix of `abc` only, the scan falls back to `a`
+        assert_eq!(matches(&dfa, "abx"), Some((3, 1)));
+        assert_eq!(matches(&dfa, "x"), None);
+    }
+
+    #[test]
+    fn higher_priority_then_earlier_rule_wins_on_the_same_text() {
+        let dfa = Dfa::new(&[("[a-z]+", 1), ("if", 2), ("i[a-z]", 2)]).unwrap();
+        assert_eq!(matches(&dfa, "if("), Some((1, 2)));
+        assert_eq!(matches(&dfa, "iffy"), Some((0, 4)));
+        assert_eq!(matches(&dfa, "in"), Some((2, 2)));
+        assert_eq!(matches(&dfa, "x"), Some((0, 1)));
+    }
+
+    #[test]
+    fn patterns_support_classes_escapes_and_repetition() {
+        let dfa = Dfa::new(&[
+            (r"\d+(_\d+)*", 1),
+            (r"[^a-z\s]", 0),
+            (r"0x[0-9a-f]+|ab?c", 1),
+        ])
+        .unwrap();
+        assert_eq!(matches(&dfa, "1_000_"), Some((0, 5)));
+        assert_eq!(matches(&dfa, "0x1f"), Some((2, 4)));
+        assert_eq!(matches(&dfa, "ac"), Some((2, 2)));
+        assert_eq!(matches(&dfa, "abc"), Some((2, 3)));
+        assert_eq!(matches(&dfa, "#"), Some((1, 1)));
+        assert_eq!(matches(&dfa, " "), None);
+        assert!(Dfa::new(&[("(a", 1)]).is_err());
+    }
+}
@@ -0,0 +1,1102 @@
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+pub const EPSILON: &str = "ε";
+pub const END_MARKER: &str = "$";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Production {
+    pub lhs: String,
+    pub rhs: Vec<String>,
+    /// terminal named by `%prec`, overriding the last terminal of the rhs
+    pub precedence: Option<String>,
+    /// line of the grammar file it was read from, 0 for the augmented start production
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precedence {
+    pub level: usize,
+    pub associativity: Associativity,
+}
+
+impl fmt::Display for Production {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.rhs.is_empty() {
+            write!(f, "{} -> {}", self.lhs, EPSILON)
+        } else {
+            write!(f, "{} -> {}", self.lhs, self.rhs.join(" "))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GrammarError {
+    pub message: String,
+    pub line: usize,
+}
+
+/**
+ Context free grammar read from a BNF file. Production 0 is the augmented
+ start production `S' -> S`.
+*/
+#[derive(Debug)]
+pub struct Grammar {
+    pub productions: Vec<Production>,
+    pub nonterminals: Vec<String>,
+    pub terminals: Vec<String>,
+    pub precedence: HashMap<String, Precedence>,
+    pub nullable: HashSet<String>,
+    pub first: HashMap<String, BTreeSet<String>>,
+    pub follow: HashMap<String, BTreeSet<String>>,
+}
+
+/**
+ LR(0) item: a production with a dot position in its right-hand side
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Item {
+    pub production: usize,
+    pub dot: usize,
+}
+
+/**
+ Item sets of an LR automaton. Each item maps to its lookahead terminals,
+ which are left empty for LR(0) states.
+*/
+#[derive(Debug)]
+pub struct Automaton {
+    pub states: Vec<BTreeMap<Item, BTreeSet<String>>>,
+    pub transitions: Vec<BTreeMap<String, usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableMode {
+    Slr,
+    Lalr,
+    Lr1,
+}
+
+impl TableMode {
+    pub const ALL: [TableMode; 3] = [TableMode::Slr, TableMode::Lalr, TableMode::Lr1];
+
+    pub fn from_name(name: &str) -> Option<TableMode> {
+        match name.to_lowercase().as_str() {
+            "slr" => Some(TableMode::Slr),
+            "lalr" => Some(TableMode::Lalr),
+            "lr1" => Some(TableMode::Lr1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TableMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableMode::Slr => write!(f, "SLR(1)"),
+            TableMode::Lalr => write!(f, "LALR(1)"),
+            TableMode::Lr1 => write!(f, "LR(1)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LrAction {
+    Shift(usize),
+    Reduce(usize),
+    Accept,
+}
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub state: usize,
+    pub lookahead: String,
+    pub actions: Vec<LrAction>,
+}
+
+#[derive(Debug)]
+pub struct LrTable {
+    pub states: Vec<BTreeMap<Item, BTreeSet<String>>>,
+    pub action: Vec<BTreeMap<String, LrAction>>,
+    pub goto: Vec<BTreeMap<String, usize>>,
+    pub conflicts: Vec<Conflict>,
+    /// conflicts settled by precedence/associativity declarations
+    pub resolved: usize,
+    /// cells emptied by a `%nonassoc` declaration, they stay syntax errors
+    errors: BTreeSet<(usize, String)>,
+}
+
+/**
+ Parses BNF productions, one rule per line:
+
+   command-list-nt ::= command-nt SEMICOLON command-list-nt
+                     | ε
+
+ Symbols appearing on a left-hand side are nonterminals, every other symbol
+ is a terminal. The first rule defines the start symbol. `#` starts a comment.
+
+ Operator precedence is declared yacc style, one level per line with later
+ lines binding tighter, and an alternative can borrow a terminal's level
+ with `%prec`:
+
+   %left PLUS SUBTRACT
+   %right EXPONENT
+   exp-nt ::= SUBTRACT exp-nt %prec EXPONENT
+*/
+pub fn parse_grammar(text: &str) -> Result<Grammar, GrammarError> {
+    let mut rules: Vec<Production> = Vec::new();
+    let mut current_lhs: Option<String> = None;
+    let mut precedence: HashMap<String, Precedence> = HashMap::new();
+    let mut level = 0;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('%') && !line.starts_with("%prec") {
+            let mut words = line.split_whitespace();
+            let associativity = match words.next() {
+                Some("%left") => Associativity::Left,
+                Some("%right") => Associativity::Right,
+                Some("%nonassoc") => Associativity::NonAssoc,
+                Some(other) => {
+                    return Err(GrammarError {
+                        message: format!("Unknown declaration '{}'", other),
+                        line: line_number,
+                    })
+                }
+                None => unreachable!(),
+            };
+            level += 1;
+            for terminal in words {
+                precedence.insert(
+                    terminal.to_string(),
+                    Precedence {
+                        level,
+                        associativity,
+                    },
+                );
+            }
+            current_lhs = None;
+            continue;
+        }
+
+        let alternatives = if let Some(rest) = line.strip_prefix('|') {
+            if current_lhs.is_none() {
+                return Err(GrammarError {
+                    message: "Alternative without a rule".to_string(),
+                    line: line_number,
+                });
+            }
+            format!("|{}", rest)
+        } else if let Some((lhs, rest)) = line.split_once("::=") {
+            let lhs = lhs.trim();
+            if lhs.is_empty() || lhs.contains(char::is_whitespace) || lhs == END_MARKER {
+                return Err(GrammarError {
+                    message: format!("Invalid left-hand side '{}'", lhs),
+                    line: line_number,
+                });
+            }
+            current_lhs = Some(lhs.to_string());
+            rest.to_string()
+        } else {
+            return Err(GrammarError {
+                message: format!("Expected '::=' in '{}'", line),
+                line: line_number,
+            });
+        };
+
+        let lhs = current_lhs.clone().unwrap();
+        for (position, alternative) in alternatives.split('|').enumerate() {
+            // a leading '|' produces an empty first piece that is not an alternative
+            if position == 0 && alternatives.starts_with('|') {
+                continue;
+            }
+            let mut rhs: Vec<String> = alternative
+                .split_whitespace()
+                .filter(|symbol| *symbol != EPSILON)
+                .map(|symbol| symbol.to_string())
+                .collect();
+            let mut production_precedence = None;
+            if let Some(position) = rhs.iter().position(|symbol| symbol == "%prec") {
+                if position + 2 != rhs.len() {
+                    return Err(GrammarError {
+                        message: "%prec must be followed by exactly one terminal".to_string(),
+                        line: line_number,
+                    });
+                }
+                production_precedence = rhs.pop();
+                rhs.pop();
+            }
+            rules.push(Production {
+                lhs: lhs.clone(),
+                rhs,
+                precedence: production_precedence,
+                line: line_number,
+            });
+        }
+    }
+
+    let start = match rules.first() {
+        Some(rule) => rule.lhs.clone(),
+        None => {
+            return Err(GrammarError {
+                message: "Grammar has no productions".to_string(),
+                line: 0,
+            })
+        }
+    };
+
+    let mut productions = vec![Production {
+        lhs: format!("{}'", start),
+        rhs: vec![start],
+        precedence: None,
+        line: 0,
+    }];
+    productions.extend(rules);
+
+    let mut nonterminals: Vec<String> = Vec::new();
+    for production in &productions {
+        if !nonterminals.contains(&production.lhs) {
+            nonterminals.push(production.lhs.clone());
+        }
+    }
+    let mut terminals: Vec<String> = Vec::new();
+    for production in &productions {
+        for symbol in &production.rhs {
+            if !nonterminals.contains(symbol) && !terminals.contains(symbol) {
+                terminals.push(symbol.clone());
+            }
+        }
+    }
+    terminals.push(END_MARKER.to_string());
+
+    for production in &productions {
+        if let Some(terminal) = &production.precedence {
+            if !precedence.contains_key(terminal) {
+                return Err(GrammarError {
+                    message: format!("%prec {} has no declared precedence", terminal),
+                    line: production.line,
+                });
+            }
+        }
+    }
+
+    let mut grammar = Grammar {
+        productions,
+        nonterminals,
+        terminals,
+        precedence,
+        nullable: HashSet::new(),
+        first: HashMap::new(),
+        follow: HashMap::new(),
+    };
+    grammar.compute_first();
+    grammar.compute_follow();
+    Ok(grammar)
+}
+
+impl Grammar {
+    pub fn is_nonterminal(&self, symbol: &str) -> bool {
+        self.nonterminals.iter().any(|nt| nt == symbol)
+    }
+
+    /**
+     Precedence of a production: its `%prec` terminal, otherwise the last
+     terminal of the rhs that has a declared precedence
+    */
+    pub fn production_precedence(&self, index: usize) -> Option<Precedence> {
+        let production = &self.productions[index];
+        match &production.precedence {
+            Some(terminal) => self.precedence.get(terminal).copied(),
+            None => production
+                .rhs
+                .iter()
+                .rev()
+                .filter(|symbol| !self.is_nonterminal(symbol))
+                .find_map(|symbol| self.precedence.get(symbol).copied()),
+        }
+    }
+
+    pub fn format_item(&self, item: &Item) -> String {
+        let production = &self.productions[item.production];
+        let mut symbols: Vec<&str> = production.rhs.iter().map(|s| s.as_str()).collect();
+        symbols.insert(item.dot, ".");
+        format!("{} -> {}", production.lhs, symbols.join(" "))
+    }
+
+    fn compute_first(&mut self) {
+        for terminal in &self.terminals {
+            self.first
+                .insert(terminal.clone(), BTreeSet::from([terminal.clone()]));
+        }
+        for nonterminal in &self.nonterminals {
+            self.first.insert(nonterminal.clone(), BTreeSet::new());
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for production in &self.productions {
+                let (first, nullable) = self.first_of_sequence(&production.rhs);
+                if nullable && self.nullable.insert(production.lhs.clone()) {
+                    changed = true;
+                }
+                let entry = self.first.get_mut(&production.lhs).unwrap();
+                for terminal in first {
+                    changed |= entry.insert(terminal);
+                }
+            }
+        }
+    }
+
+    fn compute_follow(&mut self) {
+        for nonterminal in &self.nonterminals {
+            self.follow.insert(nonterminal.clone(), BTreeSet::new());
+        }
+        self.follow
+            .get_mut(&self.productions[0].lhs)
+            .unwrap()
+            .insert(END_MARKER.to_string());
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for production in &self.productions {
+                for (position, symbol) in production.rhs.iter().enumerate() {
+                    if !self.is_nonterminal(symbol) {
+                        continue;
+                    }
+                    let (mut additions, nullable) =
+                        self.first_of_sequence(&production.rhs[position + 1..]);
+                    if nullable {
+                        additions.extend(self.follow[&production.lhs].iter().cloned());
+                    }
+                    let entry = self.follow.get_mut(symbol).unwrap();
+                    for terminal in additions {
+                        changed |= entry.insert(terminal);
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     FIRST set of a sequence of symbols and whether the whole sequence is nullable
+    */
+    pub fn first_of_sequence(&self, symbols: &[String]) -> (BTreeSet<String>, bool) {
+        let mut first = BTreeSet::new();
+        for symbol in symbols {
+            if let Some(symbol_first) = self.first.get(symbol) {
+                first.extend(symbol_first.iter().cloned());
+            }
+            if !self.nullable.contains(symbol) {
+                return (first, false);
+            }
+        }
+        (first, true)
+    }
+
+    fn symbol_after_dot(&self, item: &Item) -> Option<&String> {
+        self.productions[item.production].rhs.get(item.dot)
+    }
+
+    pub fn closure(&self, items: &BTreeSet<Item>) -> BTreeSet<Item> {
+        let mut closure = items.clone();
+        let mut pending: Vec<Item> = items.iter().cloned().collect();
+        while let Some(item) = pending.pop() {
+            if let Some(symbol) = self.symbol_after_dot(&item) {
+                for (index, production) in self.productions.iter().enumerate() {
+                    if &production.lhs == symbol {
+                        let new_item = Item {
+                            production: index,
+                            dot: 0,
+                        };
+                        if closure.insert(new_item) {
+                            pending.push(new_item);
+                        }
+                    }
+                }
+            }
+        }
+        closure
+    }
+
+    pub fn goto(&self, items: &BTreeSet<Item>, symbol: &str) -> BTreeSet<Item> {
+        let kernel: BTreeSet<Item> = items
+            .iter()
+            .filter(|item| self.symbol_after_dot(item).map(|s| s.as_str()) == Some(symbol))
+            .map(|item| Item {
+                production: item.production,
+                dot: item.dot + 1,
+            })
+            .collect();
+        self.closure(&kernel)
+    }
+
+    /**
+     Canonical collection of LR(0) item sets, numbered in discovery order
+    */
+    pub fn lr0_automaton(&self) -> Automaton {
+        let initial = self.closure(&BTreeSet::from([Item {
+            production: 0,
+            dot: 0,
+        }]));
+        let mut states = vec![initial];
+        let mut transitions: Vec<BTreeMap<String, usize>> = Vec::new();
+
+        let mut index = 0;
+        while index < states.len() {
+            let mut symbols: Vec<String> = Vec::new();
+            for item in &states[index] {
+                if let Some(symbol) = self.symbol_after_dot(item) {
+                    if !symbols.contains(symbol) {
+                        symbols.push(symbol.clone());
+                    }
+                }
+            }
+
+            let mut edges = BTreeMap::new();
+            for symbol in symbols {
+                let target = self.goto(&states[index], &symbol);
+                let target_index = match states.iter().position(|state| state == &target) {
+                    Some(position) => position,
+                    None => {
+                        states.push(target);
+                        states.len() - 1
+                    }
+                };
+                edges.insert(symbol, target_index);
+            }
+            transitions.push(edges);
+            index += 1;
+        }
+
+        Automaton {
+            states: states
+                .into_iter()
+                .map(|items| {
+                    items
+                        .into_iter()
+                        .map(|item| (item, BTreeSet::new()))
+                        .collect()
+                })
+                .collect(),
+            transitions,
+        }
+    }
+
+    fn lr1_closure(&self, items: &BTreeSet<(Item, String)>) -> BTreeSet<(Item, String)> {
+        let mut closure = items.clone();
+        let mut pending: Vec<(Item, String)> = items.iter().cloned().collect();
+        while let Some((item, lookahead)) = pending.pop() {
+            let symbol = match self.symbol_after_dot(&item) {
+                Some(symbol) if self.is_nonterminal(symbol) => symbol,
+                _ => continue,
+            };
+            let rest = &self.productions[item.production].rhs[item.dot + 1..];
+            let (mut lookaheads, nullable) = self.first_of_sequence(rest);
+            if nullable {
+                lookaheads.insert(lookahead);
+            }
+            for (index, production) in self.productions.iter().enumerate() {
+                if &production.lhs != symbol {
+                    continue;
+                }
+                for terminal in &lookaheads {
+                    let new_item = (
+                        Item {
+                            production: index,
+                            dot: 0,
+                        },
+                        terminal.clone(),
+                    );
+                    if closure.insert(new_item.clone()) {
+                        pending.push(new_item);
+                    }
+                }
+            }
+        }
+        closure
+    }
+
+    /**
+     Canonical collection of LR(1) item sets
+    */
+    pub fn lr1_automaton(&self) -> Automaton {
+        let initial = self.lr1_closure(&BTreeSet::from([(
+            Item {
+                production: 0,
+                dot: 0,
+            },
+            END_MARKER.to_string(),
+        )]));
+        let mut states = vec![initial];
+        let mut transitions: Vec<BTreeMap<String, usize>> = Vec::new();
+
+        let mut index = 0;
+        while index < states.len() {
+            let mut symbols: Vec<String> = Vec::new();
+            for (item, _) in &states[index] {
+                if let Some(symbol) = self.symbol_after_dot(item) {
+                    if !symbols.contains(symbol) {
+                        symbols.push(symbol.clone());
+                    }
+                }
+            }
+
+            let mut edges = BTreeMap::new();
+            for symbol in symbols {
+                let kernel: BTreeSet<(Item, String)> = states[index]
+                    .iter()
+                    .filter(|(item, _)| self.symbol_after_dot(item) == Some(&symbol))
+                    .map(|(item, lookahead)| {
+                        (
+                            Item {
+                                production: item.production,
+                                dot: item.dot + 1,
+                            },
+                            lookahead.clone(),
+                        )
+                    })
+                    .collect();
+                let target = self.lr1_closure(&kernel);
+                let target_index = match states.iter().position(|state| state == &target) {
+                    Some(position) => position,
+                    None => {
+                        states.push(target);
+                        states.len() - 1
+                    }
+                };
+                edges.insert(symbol, target_index);
+            }
+            transitions.push(edges);
+            index += 1;
+        }
+
+        let states = states
+            .into_iter()
+            .map(|items| {
+                let mut grouped: BTreeMap<Item, BTreeSet<String>> = BTreeMap::new();
+                for (item, lookahead) in items {
+                    grouped.entry(item).or_default().insert(lookahead);
+                }
+                grouped
+            })
+            .collect();
+        Automaton {
+            states,
+            transitions,
+        }
+    }
+
+    /**
+     LALR(1) automaton: LR(1) states sharing the same LR(0) core are merged
+     and their lookaheads united
+    */
+    pub fn lalr_automaton(&self) -> Automaton {
+        let canonical = self.lr1_automaton();
+        let mut cores: Vec<BTreeSet<Item>> = Vec::new();
+        let mut merged_index: Vec<usize> = Vec::new();
+        for state in &canonical.states {
+            let core: BTreeSet<Item> = state.keys().cloned().collect();
+            let index = match cores.iter().position(|existing| existing == &core) {
+                Some(position) => position,
+                None => {
+                    cores.push(core);
+                    cores.len() - 1
+                }
+            };
+            merged_index.push(index);
+        }
+
+        let mut states: Vec<BTreeMap<Item, BTreeSet<String>>> = vec![BTreeMap::new(); cores.len()];
+        let mut transitions: Vec<BTreeMap<String, usize>> = vec![BTreeMap::new(); cores.len()];
+        for (index, state) in canonical.states.into_iter().enumerate() {
+            let merged = merged_index[index];
+            for (item, lookaheads) in state {
+                states[merged].entry(item).or_default().extend(lookaheads);
+            }
+            for (symbol, target) in &canonical.transitions[index] {
+                transitions[merged].insert(symbol.clone(), merged_index[*target]);
+            }
+        }
+        Automaton {
+            states,
+            transitions,
+        }
+    }
+
+    pub fn build_table(&self, mode: TableMode) -> LrTable {
+        match mode {
+            TableMode::Slr => self.table_from_automaton(self.lr0_automaton(), true),
+            TableMode::Lalr => self.table_from_automaton(self.lalr_automaton(), false),
+            TableMode::Lr1 => self.table_from_automaton(self.lr1_automaton(), false),
+        }
+    }
+
+    /**
+     ACTION/GOTO table of an automaton. Completed items reduce on their own
+     lookaheads, or on FOLLOW of the left-hand side when `use_follow` is set.
+    */
+    fn table_from_automaton(&self, automaton: Automaton, use_follow: bool) -> LrTable {
+        let mut table = LrTable {
+            states: Vec::new(),
+            action: vec![BTreeMap::new(); automaton.states.len()],
+            goto: vec![BTreeMap::new(); automaton.states.len()],
+            conflicts: Vec::new(),
+            resolved: 0,
+            errors: BTreeSet::new(),
+        };
+
+        for (state, items) in automaton.states.iter().enumerate() {
+            for (symbol, target) in &automaton.transitions[state] {
+                if self.is_nonterminal(symbol) {
+                    table.goto[state].insert(symbol.clone(), *target);
+                } else {
+                    table.add_action(self, state, symbol, LrAction::Shift(*target));
+                }
+            }
+            for (item, lookaheads) in items {
+                let production = &self.productions[item.production];
+                if item.dot < production.rhs.len() {
+                    continue;
+                }
+                if item.production == 0 {
+                    table.add_action(self, state, END_MARKER, LrAction::Accept);
+                    continue;
+                }
+                let lookaheads = if use_follow {
+                    &self.follow[&production.lhs]
+                } else {
+                    lookaheads
+                };
+                for terminal in lookaheads {
+                    table.add_action(self, state, terminal, LrAction::Reduce(item.production));
+                }
+            }
+        }
+        table.states = automaton.states;
+        table
+    }
+}
+
+/**
+ Settles a shift/reduce pair from the declared precedences. `Some(None)` means
+ a `%nonassoc` operator met itself and the cell becomes an error.
+*/
+fn resolve_by_precedence(
+    grammar: &Grammar,
+    terminal: &str,
+    shift: LrAction,
+    reduce: LrAction,
+) -> Option<Option<LrAction>> {
+    let production = match reduce {
+        LrAction::Reduce(production) => production,
+        _ => return None,
+    };
+    let token_precedence = grammar.precedence.get(terminal)?;
+    let production_precedence = grammar.production_precedence(production)?;
+
+    if token_precedence.level > production_precedence.level {
+        Some(Some(shift))
+    } else if token_precedence.level < production_precedence.level {
+        Some(Some(reduce))
+    } else {
+        match token_precedence.associativity {
+            Associativity::Left => Some(Some(reduce)),
+            Associativity::Right => Some(Some(shift)),
+            Associativity::NonAssoc => Some(None),
+        }
+    }
+}
+
+impl LrTable {
+    /**
+     Inserts an action. When the cell is already taken the conflict is settled
+     by precedence declarations if possible, otherwise it is recorded and the
+     cell keeps the shift (or the earlier production) so the table stays usable.
+    */
+    fn add_action(&mut self, grammar: &Grammar, state: usize, terminal: &str, action: LrAction) {
+        if self.errors.contains(&(state, terminal.to_string())) {
+            return;
+        }
+        let row = &mut self.action[state];
+        let existing = match row.get(terminal) {
+            None => {
+                row.insert(terminal.to_string(), action);
+                return;
+            }
+            Some(existing) if *existing == action => return,
+            Some(existing) => *existing,
+        };
+
+        let resolution = match (existing, action) {
+            (LrAction::Shift(_), LrAction::Reduce(_)) => {
+                resolve_by_precedence(grammar, terminal, existing, action)
+            }
+            (LrAction::Reduce(_), LrAction::Shift(_)) => {
+                resolve_by_precedence(grammar, terminal, action, existing)
+            }
+            _ => None,
+        };
+        if let Some(winner) = resolution {
+            self.resolved += 1;
+            match winner {
+                Some(winner) => {
+                    row.insert(terminal.to_string(), winner);
+                }
+                None => {
+                    row.remove(terminal);
+                    self.errors.insert((state, terminal.to_string()));
+                }
+            }
+            return;
+        }
+
+        let keep_new = match (existing, action) {
+            (LrAction::Reduce(_), LrAction::Shift(_)) => true,
+            (LrAction::Reduce(old), LrAction::Reduce(new)) => new < old,
+            _ => false,
+        };
+        if keep_new {
+            row.insert(terminal.to_string(), action);
+        }
+
+        match self
+            .conflicts
+            .iter_mut()
+            .find(|conflict| conflict.state == state && conflict.lookahead == terminal)
+        {
+            Some(conflict) => {
+                if !conflict.actions.contains(&action) {
+                    conflict.actions.push(action);
+                }
+            }
+            None => self.conflicts.push(Conflict {
+                state,
+                lookahead: terminal.to_string(),
+                actions: vec![existing, action],
+            }),
+        }
+    }
+
+    /**
+     Human readable report listing, for every conflict, the items of the state
+     that produce the competing actions
+    */
+    pub fn conflict_report(&self, grammar: &Grammar) -> String {
+        let mut report = String::new();
+        for conflict in &self.conflicts {
+            let kind = if conflict
+                .actions
+                .iter()
+                .any(|action| matches!(action, LrAction::Shift(_)))
+            {
+                "shift/reduce"
+            } else {
+                "reduce/reduce"
+            };
+            report.push_str(&format!(
+                "State {}: {} conflict on {}\n",
+                conflict.state, kind, conflict.lookahead
+            ));
+            report.push_str("  items:\n");
+            for (item, lookaheads) in &self.states[conflict.state] {
+                let production = &grammar.productions[item.production];
+                let relevant = match production.rhs.get(item.dot) {
+                    Some(symbol) => symbol == &conflict.lookahead,
+                    None => conflict.actions.iter().any(|action| match action {
+                        LrAction::Reduce(index) => *index == item.production,
+                        LrAction::Accept => item.production == 0,
+                        LrAction::Shift(_) => false,
+                    }),
+                };
+                if !relevant {
+                    continue;
+                }
+                if lookaheads.is_empty() {
+                    report.push_str(&format!("    {}\n", grammar.format_item(item)));
+                } else {
+                    let lookaheads: Vec<&str> = lookaheads.iter().map(|s| s.as_str()).collect();
+                    report.push_str(&format!(
+                        "    [{}, {}]\n",
+                        grammar.format_item(item),
+                        lookaheads.join(" ")
+                    ));
+                }
+            }
+            report.push_str("  actions:\n");
+            for action in &conflict.actions {
+                match action {
+                    LrAction::Reduce(index) => {
+                        report.push_str(&format!("    reduce by {}\n", grammar.productions[*index]))
+                    }
+                    _ => report.push_str(&format!("    {}\n", action)),
+                }
+            }
+        }
+        report
+    }
+
+    /**
+     Table in the syntax_table.json shape read by the parser
+    */
+    pub fn to_json(&self, grammar: &Grammar) -> Value {
+        let mut states = Map::new();
+        for (state, row) in self.action.iter().enumerate() {
+            let mut actions = Map::new();
+            for (terminal, action) in row {
+                let action = match action {
+                    LrAction::Shift(target) => format!("S {}", target),
+                    LrAction::Reduce(index) => {
+                        let production = &grammar.productions[*index];
+                        format!("R {} {}", production.rhs.len(), production.lhs)
+                    }
+                    LrAction::Accept => "ACC".to_string(),
+                };
+                actions.insert(terminal.clone(), Value::String(action));
+            }
+            let mut gotos = Map::new();
+            for (nonterminal, target) in &self.goto[state] {
+                gotos.insert(nonterminal.clone(), json!({ "ANY": target }));
+            }
+            states.insert(
+                state.to_string(),
+                json!({ "ACTION": actions, "GOTO": gotos }),
+            );
+        }
+        Value::Object(states)
+    }
+}
+
+impl fmt::Display for LrAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LrAction::Shift(target) => write!(f, "shift {}", target),
+            LrAction::Reduce(production) => write!(f, "reduce {}", production),
+            LrAction::Accept => write!(f, "accept"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(symbols: &[&str]) -> BTreeSet<String> {
+        symbols.iter().map(|symbol| symbol.to_string()).collect()
+    }
+
+    const EXPRESSIONS: &str = "
+        e ::= t e2
+        e2 ::= PLUS t e2 | ε
+        t ::= f t2
+        t2 ::= TIMES f t2 | ε
+        f ::= LPAREN e RPAREN | ID
+    ";
+
+    #[test]
+    fn first_sets_skip_nullable_symbols() {
+        let grammar = parse_grammar(EXPRESSIONS).unwrap();
+        assert_eq!(grammar.first["e"], set(&["ID", "LPAREN"]));
+        assert_eq!(grammar.first["t"], set(&["ID", "LPAREN"]));
+        assert_eq!(grammar.first["e2"], set(&["PLUS"]));
+        assert_eq!(grammar.first["t2"], set(&["TIMES"]));
+        assert_eq!(grammar.nullable, set(&["e2", "t2"]).into_iter().collect());
+    }
+
+    #[test]
+    fn follow_sets_flow_through_nullable_suffixes() {
+        let grammar = parse_grammar(EXPRESSIONS).unwrap();
+        assert_eq!(grammar.follow["e"], set(&["$", "RPAREN"]));
+        assert_eq!(grammar.follow["e2"], set(&["$", "RPAREN"]));
+        assert_eq!(grammar.follow["t"], set(&["$", "PLUS", "RPAREN"]));
+        assert_eq!(grammar.follow["f"], set(&["$", "PLUS", "RPAREN", "TIMES"]));
+    }
+
+    #[test]
+    fn slr_table_of_an_slr_grammar_has_no_conflicts() {
+        let grammar = parse_grammar(EXPRESSIONS).unwrap();
+        let table = grammar.build_table(TableMode::Slr);
+        assert!(table.conflicts.is_empty());
+        assert_eq!(table.action[0].get(END_MARKER), None);
+        assert!(matches!(table.action[0]["ID"], LrAction::Shift(_)));
+        assert_eq!(table.goto[0].keys().collect::<Vec<_>>(), ["e", "f", "t"]);
+    }
+
+    const AMBIGUOUS: &str = "
+        e ::= e PLUS e | e TIMES e | MINUS e | ID
+    ";
+
+    /// actions in the states where production `production` is complete, on `terminal`
+    fn reduce_state_actions(
+        grammar: &Grammar,
+        table: &LrTable,
+        production: usize,
+        terminal: &str,
+    ) -> Vec<Option<LrAction>> {
+        let complete = Item {
+            production,
+            dot: grammar.productions[production].rhs.len(),
+        };
+        table
+            .states
+            .iter()
+            .enumerate()
+            .filter(|(_, items)| items.contains_key(&complete))
+            .map(|(state, _)| table.action[state].get(terminal).copied())
+            .collect()
+    }
+
+    #[test]
+    fn conflicts_are_reported_with_their_items_and_actions() {
+        let grammar = parse_grammar(AMBIGUOUS).unwrap();
+        let table = grammar.build_table(TableMode::Slr);
+        assert!(!table.conflicts.is_empty());
+        assert_eq!(table.resolved, 0);
+        let report = table.conflict_report(&grammar);
+        assert!(report.contains("shift/reduce conflict on PLUS"), "{report}");
+        assert!(report.contains("    e -> e PLUS e .\n"), "{report}");
+        assert!(report.contains("    e -> e . PLUS e\n"), "{report}");
+        assert!(report.contains("    reduce by e -> e PLUS e\n"), "{report}");
+        assert!(report.contains("    shift "), "{report}");
+    }
+
+    #[test]
+    fn left_and_right_associativity_pick_reduce_and_shift() {
+        let left = parse_grammar(&format!("%left PLUS\n%left TIMES\n{AMBIGUOUS}")).unwrap();
+        let table = left.build_table(TableMode::Slr);
+        // `MINUS e` has no precedence, only its conflicts are left
+        let unary = Item {
+            production: 3,
+            dot: 2,
+        };
+        assert!(!table.conflicts.is_empty());
+        assert!(table
+            .conflicts
+            .iter()
+            .all(|conflict| table.states[conflict.state].contains_key(&unary)));
+        assert!(table.resolved > 0);
+        let plus = reduce_state_actions(&left, &table, 1, "PLUS");
+        assert_eq!(plus, [Some(LrAction::Reduce(1))]);
+        let times = reduce_state_actions(&left, &table, 1, "TIMES");
+        assert!(matches!(times[..], [Some(LrAction::Shift(_))]));
+        let times = reduce_state_actions(&left, &table, 2, "PLUS");
+        assert_eq!(times, [Some(LrAction::Reduce(2))]);
+
+        let right = parse_grammar(&format!("%right PLUS\n{AMBIGUOUS}")).unwrap();
+        let table = right.build_table(TableMode::Slr);
+        let plus = reduce_state_actions(&right, &table, 1, "PLUS");
+        assert!(matches!(plus[..], [Some(LrAction::Shift(_))]));
+    }
+
+    #[test]
+    fn nonassoc_operators_leave_an_error_entry() {
+        let grammar = parse_grammar("%nonassoc LESS\ne ::= e LESS e | ID").unwrap();
+        let table = grammar.build_table(TableMode::Slr);
+        assert!(table.conflicts.is_empty());
+        assert_eq!(table.resolved, 1);
+        assert_eq!(reduce_state_actions(&grammar, &table, 1, "LESS"), [None]);
+        assert_eq!(
+            reduce_state_actions(&grammar, &table, 1, END_MARKER),
+            [Some(LrAction::Reduce(1))]
+        );
+    }
+
+    #[test]
+    fn prec_gives_a_production_the_level_of_another_terminal() {
+        let grammar = parse_grammar(
+            "%left PLUS\n%left TIMES\n%right UMINUS\n\
+             e ::= e PLUS e | e TIMES e | MINUS e %prec UMINUS | ID",
+        )
+        .unwrap();
+        assert_eq!(grammar.productions[3].precedence.as_deref(), Some("UMINUS"));
+        assert_eq!(grammar.productions[3].rhs, ["MINUS", "e"]);
+        let table = grammar.build_table(TableMode::Slr);
+        assert!(table.conflicts.is_empty());
+        for terminal in ["PLUS", "TIMES"] {
+            assert_eq!(
+                reduce_state_actions(&grammar, &table, 3, terminal),
+                [Some(LrAction::Reduce(3))],
+                "-a {terminal} b"
+            );
+        }
+    }
+
+    /// assignments through pointers: not SLR(1), but LALR(1)
+    const POINTERS: &str = "
+        s ::= l EQUAL r | r
+        l ::= STAR r | ID
+        r ::= l
+    ";
+
+    /// LR(1), merging the states reducing `c` into a or b makes a reduce/reduce conflict
+    const NOT_LALR: &str = "
+        s ::= A a D | B b D | A b E | B a E
+        a ::= C
+        b ::= C
+    ";
+
+    #[test]
+    fn lalr_lookaheads_settle_what_follow_sets_cannot() {
+        let grammar = parse_grammar(POINTERS).unwrap();
+        let slr = grammar.build_table(TableMode::Slr);
+        assert_eq!(slr.conflicts.len(), 1);
+        assert_eq!(slr.conflicts[0].lookahead, "EQUAL");
+
+        let lalr = grammar.build_table(TableMode::Lalr);
+        let lr1 = grammar.build_table(TableMode::Lr1);
+        assert!(lalr.conflicts.is_empty());
+        assert!(lr1.conflicts.is_empty());
+        assert_eq!(lalr.states.len(), slr.states.len());
+        assert!(lr1.states.len() > lalr.states.len());
+    }
+
+    #[test]
+    fn merging_lr1_states_can_add_reduce_reduce_conflicts() {
+        let grammar = parse_grammar(NOT_LALR).unwrap();
+        let lr1 = grammar.build_table(TableMode::Lr1);
+        assert!(lr1.conflicts.is_empty());
+
+        let lalr = grammar.build_table(TableMode::Lalr);
+        assert_eq!(lalr.states.len() + 1, lr1.states.len());
+        let mut lookaheads: Vec<&str> = lalr
+            .conflicts
+            .iter()
+            .map(|conflict| conflict.lookahead.as_str())
+            .collect();
+        lookaheads.sort();
+        assert_eq!(lookaheads, ["D", "E"]);
+        let report = lalr.conflict_report(&grammar);
+        assert!(report.contains("reduce/reduce conflict on D"), "{report}");
+        assert!(report.contains("    [a -> C ., D E]\n"), "{report}");
+        assert!(report.contains("    [b -> C ., D E]\n"), "{report}");
+    }
+
+    #[test]
+    fn prec_without_declared_precedence_reports_its_line() {
+        let err = parse_grammar("%left PLUS\ne ::= e PLUS e\n  | MINUS e %prec UMINUS\n  | ID")
+            .unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "%prec UMINUS has no declared precedence");
+    }
+
+    #[test]
+    fn malformed_lines_report_their_line() {
+        let err = parse_grammar("e ::= ID\n\nf ID\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("Expected '::='"), "{}", err.message);
+    }
+}
@@ -0,0 +1,746 @@
+use crate::ast::{BinaryOp, Expr, Ident, Literal, Program, Stmt, Type, UnaryOp};
+use crate::lexer::Span;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/**
+ Value of a variable or an expression while the program runs. The program
+ passed semantic analysis, so operands always have the types the operators
+ expect.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+}
+
+impl Value {
+    /**
+     Value of a declared variable before its first assignment
+    */
+    pub fn default_of(ty: Type) -> Value {
+        match ty {
+            Type::Integer => Value::Integer(0),
+            Type::Float => Value::Float(0.0),
+            Type::String => Value::String(String::new()),
+            Type::Boolean => Value::Boolean(false),
+        }
+    }
+
+    /**
+     The value as a literal of the language, strings quoted and escaped
+    */
+    pub fn literal(&self) -> String {
+        match self {
+            Value::String(text) => format!("{text:?}"),
+            value => value.to_string(),
+        }
+    }
+
+    /**
+     Bytes a variable holding this value counts against the memory limit
+    */
+    pub fn size(&self) -> usize {
+        let text = match self {
+            Value::String(value) => value.len(),
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + text
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Value {
+        match literal {
+            Literal::Integer(value) => Value::Integer(*value),
+            Literal::Float(value) => Value::Float(*value),
+            Literal::String(value) => Value::String(value.clone()),
+            Literal::Boolean(value) => Value::Boolean(*value),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{value}"),
+            // debug formatting keeps the fraction of whole floats, 2.0 and not 2
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::String(value) => write!(f, "{value}"),
+            Value::Boolean(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/**
+ Why a run stopped. `Arithmetic` errors come from the program itself, the
+ limits ones from a program that ran longer or grew larger than allowed.
+ `Internal` ones are checked by the semantic analysis and never expected here.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Arithmetic,
+    StepLimit,
+    StringLimit,
+    MemoryLimit,
+    Timeout,
+    Internal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+}
+
+/**
+ Bounds on a run so a program posted to the server cannot hang a worker or
+ exhaust its memory. A step is one statement executed or one expression
+ evaluated, memory counts every live variable and everything printed.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_steps: u64,
+    pub max_string_length: usize,
+    pub max_memory: usize,
+    pub timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: 1_000_000,
+            max_string_length: 64 * 1024,
+            max_memory: 1024 * 1024,
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl Limits {
+    /**
+     Default limits overridden by the `RUN_MAX_STEPS`, `RUN_MAX_STRING_LENGTH`,
+     `RUN_MAX_MEMORY` (bytes) and `RUN_TIMEOUT_MS` environment variables
+    */
+    pub fn from_env() -> Result<Limits, String> {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|_| format!("{name} must be a number, found '{value}'")),
+                Err(_) => Ok(default),
+            }
+        }
+        let default = Limits::default();
+        Ok(Limits {
+            max_steps: var("RUN_MAX_STEPS", default.max_steps)?,
+            max_string_length: var("RUN_MAX_STRING_LENGTH", default.max_string_length)?,
+            max_memory: var("RUN_MAX_MEMORY", default.max_memory)?,
+            timeout: Duration::from_millis(var(
+                "RUN_TIMEOUT_MS",
+                default.timeout.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/**
+ What a run printed, up to the runtime error that stopped it if any
+*/
+#[derive(Debug, Serialize)]
+pub struct Execution {
+    pub output: String,
+    pub error: Option<RuntimeError>,
+}
+
+/**
+ Tree walking interpreter. Scopes nest like the ones of the semantic analysis,
+ a block pushes one and drops it when it ends.
+*/
+struct Interpreter<'a> {
+    scopes: Vec<HashMap<String, Value>>,
+    output: String,
+    budget: Budget<'a>,
+}
+
+/**
+ Steps and memory used so far by a run, checked against its limits. Shared by
+ the tree interpreter and the bytecode VM so both enforce the same limits.
+*/
+pub struct Budget<'a> {
+    limits: Limits,
+    steps: u64,
+    memory: usize,
+    // set when the timeout expires, checked on every step
+    cancelled: &'a AtomicBool,
+}
+
+fn overflow(op: BinaryOp, span: Span) -> RuntimeError {
+    RuntimeError {
+        kind: ErrorKind::Arithmetic,
+        message: format!("Overflow in {op}"),
+        span,
+    }
+}
+
+fn not_a_number(op: BinaryOp, span: Span) -> RuntimeError {
+    RuntimeError {
+        kind: ErrorKind::Arithmetic,
+        message: format!("Result of {op} is not a number"),
+        span,
+    }
+}
+
+fn division_by_zero(span: Span) -> RuntimeError {
+    RuntimeError {
+        kind: ErrorKind::Arithmetic,
+        message: "Division by zero".to_string(),
+        span,
+    }
+}
+
+fn integer_operation(
+    op: BinaryOp,
+    left: i64,
+    right: i64,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    let result = match op {
+        BinaryOp::Add => left.checked_add(right),
+        BinaryOp::Subtract => left.checked_sub(right),
+        BinaryOp::Multiply => left.checked_mul(right),
+        BinaryOp::Divide | BinaryOp::Modulus if right == 0 => return Err(division_by_zero(span)),
+        BinaryOp::Divide => left.checked_div(right),
+        BinaryOp::Modulus => left.checked_rem(right),
+        BinaryOp::Exponent => {
+            if right < 0 {
+                return Err(RuntimeError {
+                    kind: ErrorKind::Arithmetic,
+                    message: format!("Negative exponent {right} on integer {left}"),
+                    span,
+                });
+            }
+            u32::try_from(right)
+                .ok()
+                .and_then(|exponent| left.checked_pow(exponent))
+        }
+        _ => return Ok(Value::Boolean(compare(op, left.cmp(&right)))),
+    };
+    result.map(Value::Integer).ok_or_else(|| overflow(op, span))
+}
+
+fn float_operation(op: BinaryOp, left: f64, right: f64, span: Span) -> Result<Value, RuntimeError> {
+    let result = match op {
+        BinaryOp::Add => left + right,
+        BinaryOp::Subtract => left - right,
+        BinaryOp::Multiply => left * right,
+        BinaryOp::Divide | BinaryOp::Modulus if right == 0.0 => return Err(division_by_zero(span)),
+        BinaryOp::Divide => left / right,
+        BinaryOp::Modulus => left % right,
+        BinaryOp::Exponent => left.powf(right),
+        _ => {
+            let ordering = left.partial_cmp(&right).expect("floats are always finite");
+            return Ok(Value::Boolean(compare(op, ordering)));
+        }
+    };
+    // literals are finite, keep every computed float finite as well
+    if result.is_nan() {
+        return Err(not_a_number(op, span));
+    }
+    if result.is_infinite() {
+        return Err(overflow(op, span));
+    }
+    Ok(Value::Float(result))
+}
+
+fn compare(op: BinaryOp, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        BinaryOp::Greater => ordering.is_gt(),
+        BinaryOp::Less => ordering.is_lt(),
+        BinaryOp::GreaterEqual => ordering.is_ge(),
+        BinaryOp::LessEqual => ordering.is_le(),
+        BinaryOp::Equal => ordering.is_eq(),
+        BinaryOp::NotEqual => ordering.is_ne(),
+        _ => unreachable!("{op} is not a comparison"),
+    }
+}
+
+pub fn unary_operation(op: UnaryOp, operand: Value, span: Span) -> Result<Value, RuntimeError> {
+    match (op, operand) {
+        (UnaryOp::Negate, Value::Integer(value)) => value
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| RuntimeError {
+                kind: ErrorKind::Arithmetic,
+                message: format!("Overflow in {op}"),
+                span,
+            }),
+        (UnaryOp::Negate, Value::Float(value)) => Ok(Value::Float(-value)),
+        (UnaryOp::Not, Value::Boolean(value)) => Ok(Value::Boolean(!value)),
+        (_, value) => Err(RuntimeError {
+            kind: ErrorKind::Internal,
+            message: format!("Operator {op} cannot be applied to {value:?}"),
+            span,
+        }),
+    }
+}
+
+pub fn binary_operation(
+    op: BinaryOp,
+    left: Value,
+    right: Value,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Integer(left), Value::Integer(right)) => integer_operation(op, left, right, span),
+        (Value::Float(left), Value::Float(right)) => float_operation(op, left, right, span),
+        (Value::String(mut left), Value::String(right)) => match op {
+            BinaryOp::Add => {
+                left.push_str(&right);
+                Ok(Value::String(left))
+            }
+            _ => Ok(Value::Boolean(compare(op, left.cmp(&right)))),
+        },
+        (Value::Boolean(left), Value::Boolean(right)) => {
+            Ok(Value::Boolean(compare(op, left.cmp(&right))))
+        }
+        (left, right) => Err(RuntimeError {
+            kind: ErrorKind::Internal,
+            message: format!("Operator {op} cannot be applied to {left:?} and {right:?}"),
+            span,
+        }),
+    }
+}
+
+impl<'a> Budget<'a> {
+    pub fn new(limits: Limits, cancelled: &'a AtomicBool) -> Budget<'a> {
+        Budget {
+            limits,
+            steps: 0,
+            memory: 0,
+            cancelled,
+        }
+    }
+
+    pub fn step(&mut self, span: Span) -> Result<(), RuntimeError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(RuntimeError {
+                kind: ErrorKind::Timeout,
+                message: format!(
+                    "Program did not finish within {} ms",
+                    self.limits.timeout.as_millis()
+                ),
+                span,
+            });
+        }
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(RuntimeError {
+                kind: ErrorKind::StepLimit,
+                message: format!(
+                    "Program did not finish within {} steps",
+                    self.limits.max_steps
+                ),
+                span,
+            });
+        }
+        Ok(())
+    }
+
+    /**
+     Accounts for `added` more bytes and `removed` fewer, failing past the memory limit
+    */
+    pub fn allocate(
+        &mut self,
+        added: usize,
+        removed: usize,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        self.memory = self.memory + added - removed;
+        if self.memory > self.limits.max_memory {
+            return Err(RuntimeError {
+                kind: ErrorKind::MemoryLimit,
+                message: format!(
+                    "Program uses more than {} bytes of memory",
+                    self.limits.max_memory
+                ),
+                span,
+            });
+        }
+        Ok(())
+    }
+
+    /**
+     Fails when a string built by the program is longer than the limit
+    */
+    pub fn check_string(&self, value: &Value, span: Span) -> Result<(), RuntimeError> {
+        match value {
+            Value::String(value) if value.len() > self.limits.max_string_length => {
+                Err(RuntimeError {
+                    kind: ErrorKind::StringLimit,
+                    message: format!(
+                        "String of {} bytes is longer than the limit of {}",
+                        value.len(),
+                        self.limits.max_string_length
+                    ),
+                    span,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     Gives back the bytes of values that went out of scope
+    */
+    pub fn free(&mut self, size: usize) {
+        self.memory -= size;
+    }
+}
+
+impl Interpreter<'_> {
+    fn lookup(&self, name: &Ident) -> Result<&Value, RuntimeError> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.name))
+            .ok_or_else(|| RuntimeError {
+                kind: ErrorKind::Internal,
+                message: format!("Variable {} is not declared", name.name),
+                span: name.span,
+            })
+    }
+
+    fn assign(&mut self, name: &Ident, value: Value) -> Result<(), RuntimeError> {
+        let slot = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.name))
+            .ok_or_else(|| RuntimeError {
+                kind: ErrorKind::Internal,
+                message: format!("Variable {} is not declared", name.name),
+                span: name.span,
+            })?;
+        let (added, removed) = (value.size(), slot.size());
+        *slot = value;
+        self.budget.allocate(added, removed, name.span)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.budget.step(expr.span())?;
+        match expr {
+            Expr::Literal { value, .. } => Ok(Value::from(value)),
+            Expr::Var(name) => self.lookup(name).cloned(),
+            Expr::Unary { op, operand, span } => {
+                let operand = self.eval(operand)?;
+                unary_operation(*op, operand, *span)
+            }
+            Expr::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                left,
+                right,
+                ..
+            } => {
+                let left = self.eval(left)?;
+                // the left operand decides: false && _, true || _
+                if left == Value::Boolean(*op == BinaryOp::Or) {
+                    return Ok(left);
+                }
+                self.eval(right)
+            }
+            Expr::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                let result = binary_operation(*op, left, right, *span)?;
+                self.budget.check_string(&result, *span)?;
+                Ok(result)
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr) -> Result<bool, RuntimeError> {
+        match self.eval(condition)? {
+            Value::Boolean(value) => Ok(value),
+            value => Err(RuntimeError {
+                kind: ErrorKind::Internal,
+                message: format!("Condition must be boolean, found {value:?}"),
+                span: condition.span(),
+            }),
+        }
+    }
+
+    fn exec(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.budget.step(stmt.span())?;
+        match stmt {
+            Stmt::Declare { name, ty, .. } => {
+                let value = Value::default_of(*ty);
+                self.budget.allocate(value.size(), 0, name.span)?;
+                let scope = self.scopes.last_mut().expect("expects a scope");
+                scope.insert(name.name.clone(), value);
+            }
+            Stmt::Assign { name, value, .. } => {
+                let value = self.eval(value)?;
+                self.assign(name, value)?;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if self.condition(condition)? {
+                    self.exec(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.exec(else_branch)?;
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while self.condition(condition)? {
+                    self.exec(body)?;
+                }
+            }
+            Stmt::Print { value, span } => {
+                let line = format!("{}\n", self.eval(value)?);
+                self.budget.allocate(line.len(), 0, *span)?;
+                self.output.push_str(&line);
+            }
+            Stmt::Block { body, .. } => {
+                self.scopes.push(HashMap::new());
+                let executed = body.iter().try_for_each(|stmt| self.exec(stmt));
+                let scope = self.scopes.pop().expect("expects a scope");
+                self.budget
+                    .free(scope.values().map(Value::size).sum::<usize>());
+                executed?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ Runs a program that passed semantic analysis, capturing what it prints.
+ Stops with an error once `cancelled` is set.
+*/
+pub fn run(program: &Program, limits: Limits, cancelled: &AtomicBool) -> Execution {
+    let mut interpreter = Interpreter {
+        scopes: vec![HashMap::new()],
+        output: String::new(),
+        budget: Budget::new(limits, cancelled),
+    };
+    let error = program
+        .body
+        .iter()
+        .try_for_each(|stmt| interpreter.exec(stmt))
+        .err();
+    Execution {
+        output: interpreter.output,
+        error,
+    }
+}
+
+/**
+ Runs the program on a blocking thread and cancels it once the timeout
+ expires, it then stops at its next step with what it printed so far
+*/
+pub async fn run_with_timeout(program: Program, limits: Limits) -> Execution {
+    with_timeout(limits.timeout, move |cancelled| {
+        run(&program, limits, cancelled)
+    })
+    .await
+}
+
+/**
+ Calls `run` on a blocking thread, setting the flag it is given once `timeout`
+ expires. `run` must then return at its next step.
+*/
+pub async fn with_timeout<F>(timeout: Duration, run: F) -> Execution
+where
+    F: FnOnce(&AtomicBool) -> Execution + Send + 'static,
+{
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let mut task = tokio::task::spawn_blocking({
+        let cancelled = Arc::clone(&cancelled);
+        move || run(&cancelled)
+    });
+    let joined = match tokio::time::timeout(timeout, &mut task).await {
+        Ok(joined) => joined,
+        Err(_) => {
+            cancelled.store(true, Ordering::Relaxed);
+            task.await
+        }
+    };
+    joined.expect("interpreter thread panicked")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{checked, corpus_code};
+
+    fn run_code(code: &str, limits: Limits) -> Execution {
+        run(&checked(code), limits, &AtomicBool::new(false))
+    }
+
+    fn run_corpus(name: &str) -> Execution {
+        run_code(&corpus_code(name), Limits::default())
+    }
+
+    #[test]
+    fn corpus_programs_print_their_results() {
+        for (name, expected) in [
+            ("arithmetic", "22\n-964\n-22\n"),
+            ("control_flow", "2\n5\n8\n14\n"),
+            ("scopes", "10\n10\n10\n1\n"),
+            ("strings", "tab\there and \"quotes\"\ntab\there\n"),
+            ("booleans", "true\nfalse\ntrue\ntrue\n"),
+        ] {
+            let execution = run_corpus(name);
+            assert!(execution.error.is_none(), "{name}: {:?}", execution.error);
+            assert_eq!(execution.output, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn arithmetic_errors_keep_the_output_so_far() {
+        let execution = run_corpus("division_by_zero");
+        assert_eq!(execution.output, "10\n");
+        let error = execution.error.unwrap();
+        assert_eq!(error.kind, ErrorKind::Arithmetic);
+        assert_eq!(error.message, "Division by zero");
+        assert_eq!(error.span.start_line, 6);
+
+        let error = run_corpus("negative_exponent").error.unwrap();
+        assert_eq!(error.message, "Negative exponent -1 on integer 2");
+        let error = run_corpus("overflow").error.unwrap();
+        assert_eq!(error.message, "Overflow in *");
+    }
+
+    #[test]
+    fn float_results_that_are_not_numbers_are_errors() {
+        let code = "fn main() {\n  f: float;\n  f = 1.5;\n  f = (-8.0) ^ f;\n}";
+        let error = run_code(code, Limits::default()).error.unwrap();
+        assert_eq!(error.kind, ErrorKind::Arithmetic);
+        assert_eq!(error.message, "Result of ^ is not a number");
+        assert_eq!(error.span.start_line, 4);
+
+        let code = "fn main() {\n  f: float;\n  f = 1.0e300 * 1.0e300;\n}";
+        let error = run_code(code, Limits::default()).error.unwrap();
+        assert_eq!(error.message, "Overflow in *");
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            max_steps: u64::MAX,
+            ..Limits::default()
+        }
+    }
+
+    /// `count` sibling blocks each declaring a string of `length` bytes
+    fn sibling_blocks(count: usize, length: usize) -> String {
+        let text = "x".repeat(length);
+        let blocks: String = (0..count)
+            .map(|index| format!("  if (true) {{ s{index}: string; s{index} = \"{text}\"; }};\n"))
+            .collect();
+        format!("fn main() {{\n{blocks}  n: integer;\n  print(n);\n}}")
+    }
+
+    #[test]
+    fn step_limit_stops_a_long_loop() {
+        let code = "fn main() {\n  x: integer;\n  while (true) { x = x + 1; };\n}";
+        let limits = Limits {
+            max_steps: 50,
+            ..limits()
+        };
+        let error = run_code(code, limits).error.unwrap();
+        assert_eq!(error.kind, ErrorKind::StepLimit);
+        assert_eq!(error.message, "Program did not finish within 50 steps");
+        assert_eq!(error.span.start_line, 3);
+    }
+
+    #[test]
+    fn string_limit_stops_a_long_concatenation() {
+        let code = "fn main() {\n  s: string;\n  s = \"abcdef\";\n  s = s + s;\n}";
+        let limits = Limits {
+            max_string_length: 10,
+            ..limits()
+        };
+        let error = run_code(code, limits).error.unwrap();
+        assert_eq!(error.kind, ErrorKind::StringLimit);
+        assert_eq!(
+            error.message,
+            "String of 12 bytes is longer than the limit of 10"
+        );
+        assert_eq!(error.span.start_line, 4);
+    }
+
+    #[test]
+    fn memory_limit_counts_live_variables() {
+        let size = Value::String("x".repeat(100)).size();
+        let code = format!(
+            "fn main() {{\n  if (true) {{\n    a: string; b: string;\n    a = \"{0}\";\n    b = \"{0}\";\n  }};\n}}",
+            "x".repeat(100)
+        );
+        let limits = Limits {
+            max_memory: size + size / 2,
+            ..limits()
+        };
+        let error = run_code(&code, limits).error.unwrap();
+        assert_eq!(error.kind, ErrorKind::MemoryLimit);
+        assert_eq!(
+            error.message,
+            format!("Program uses more than {} bytes of memory", size + size / 2)
+        );
+        assert_eq!(error.span.start_line, 5);
+    }
+
+    #[test]
+    fn memory_is_given_back_when_a_block_ends() {
+        let size = Value::String("x".repeat(100)).size();
+        let limits = Limits {
+            max_memory: 2 * size,
+            ..limits()
+        };
+        let execution = run_code(&sibling_blocks(5, 100), limits);
+        assert!(execution.error.is_none(), "{:?}", execution.error);
+        assert_eq!(execution.output, "0\n");
+    }
+
+    #[tokio::test]
+    async fn timeout_cancels_a_run_that_never_ends() {
+        let code = "fn main() {\n  x: integer;\n\n  while (true) { x = 1; };\n}";
+        let limits = Limits {
+            timeout: Duration::from_millis(50),
+            ..limits()
+        };
+        let execution = run_with_timeout(checked(code), limits).await;
+        let error = execution.error.unwrap();
+        assert_eq!(error.kind, ErrorKind::Timeout);
+        assert_eq!(error.message, "Program did not finish within 50 ms");
+        assert_eq!(error.span.start_line, 4);
+    }
+
+    #[test]
+    fn floats_keep_their_fraction_and_sign() {
+        let execution = run_corpus("floats");
+        assert_eq!(
+            execution.output,
+            "2.75\n-0.0\n0.0\n1.4142135623730951\n1.5\n"
+        );
+    }
+}
@@ -0,0 +1,766 @@
+use crate::ast::{BinaryOp, Expr, Ident, Program, Stmt, UnaryOp};
+use crate::interpreter::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+pub type VarId = usize;
+pub type BlockId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Var(VarId),
+    Const(Value),
+}
+
+/**
+ Three-address instruction: at most one operator, its operands are variables
+ or constants and its result goes to a variable
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Copy {
+        dest: VarId,
+        src: Operand,
+    },
+    Unary {
+        dest: VarId,
+        op: UnaryOp,
+        operand: Operand,
+    },
+    Binary {
+        dest: VarId,
+        op: BinaryOp,
+        left: Operand,
+        right: Operand,
+    },
+    Print {
+        value: Operand,
+    },
+    /// SSA only: takes the argument of the predecessor control came from
+    Phi {
+        dest: VarId,
+        args: Vec<(BlockId, Operand)>,
+    },
+}
+
+impl Instr {
+    pub fn dest(&self) -> Option<VarId> {
+        match self {
+            Instr::Copy { dest, .. }
+            | Instr::Unary { dest, .. }
+            | Instr::Binary { dest, .. }
+            | Instr::Phi { dest, .. } => Some(*dest),
+            Instr::Print { .. } => None,
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut VarId> {
+        match self {
+            Instr::Copy { dest, .. }
+            | Instr::Unary { dest, .. }
+            | Instr::Binary { dest, .. }
+            | Instr::Phi { dest, .. } => Some(dest),
+            Instr::Print { .. } => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instr::Copy { src, .. } => vec![src],
+            Instr::Unary { operand, .. } => vec![operand],
+            Instr::Binary { left, right, .. } => vec![left, right],
+            Instr::Print { value } => vec![value],
+            Instr::Phi { args, .. } => args.iter().map(|(_, arg)| arg).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instr::Copy { src, .. } => vec![src],
+            Instr::Unary { operand, .. } => vec![operand],
+            Instr::Binary { left, right, .. } => vec![left, right],
+            Instr::Print { value } => vec![value],
+            Instr::Phi { args, .. } => args.iter_mut().map(|(_, arg)| arg).collect(),
+        }
+    }
+
+    /**
+     Whether the instruction can stop the program: arithmetic can overflow or
+     divide by zero and `+` can build a string past the limit. Such
+     instructions are kept even when their result is unused.
+    */
+    pub fn may_fail(&self) -> bool {
+        match self {
+            Instr::Binary { op, .. } => matches!(
+                op,
+                BinaryOp::Add
+                    | BinaryOp::Subtract
+                    | BinaryOp::Multiply
+                    | BinaryOp::Divide
+                    | BinaryOp::Modulus
+                    | BinaryOp::Exponent
+            ),
+            Instr::Unary { op, .. } => *op == UnaryOp::Negate,
+            Instr::Copy { .. } | Instr::Print { .. } | Instr::Phi { .. } => false,
+        }
+    }
+}
+
+/**
+ How a basic block ends, the only place where control flow leaves it
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        condition: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub instrs: Vec<Instr>,
+    pub terminator: Terminator,
+}
+
+impl Terminator {
+    pub fn operand(&self) -> Option<&Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Jump(_) | Terminator::Return => None,
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Jump(_) | Terminator::Return => None,
+        }
+    }
+
+    fn retarget(&mut self, map: impl Fn(BlockId) -> BlockId) {
+        match self {
+            Terminator::Jump(target) => *target = map(*target),
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => {
+                *then_block = map(*then_block);
+                *else_block = map(*else_block);
+            }
+            Terminator::Return => {}
+        }
+    }
+}
+
+impl Block {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self.terminator {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return => Vec::new(),
+        }
+    }
+}
+
+/**
+ Variable of a function. Every declaration of the source gets its own, the
+ ones shadowing an earlier declaration are numbered `x.1`, `x.2`, ...;
+ temporaries are named `%t0`, `%t1`, ... Neither can clash with a source name.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub temporary: bool,
+}
+
+/**
+ Control-flow graph of a function, its entry is the first block. The edges
+ are the successors named by each block's terminator.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub vars: Vec<Var>,
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    pub const ENTRY: BlockId = 0;
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in block.successors() {
+                if !predecessors[successor].contains(&id) {
+                    predecessors[successor].push(id);
+                }
+            }
+        }
+        predecessors
+    }
+
+    pub fn add_var(&mut self, name: String, temporary: bool) -> VarId {
+        self.vars.push(Var { name, temporary });
+        self.vars.len() - 1
+    }
+
+    /**
+     Blocks reachable from the entry, each one before its successors except
+     along back edges
+    */
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // (block, successors already pushed)
+        let mut stack = vec![(Function::ENTRY, false)];
+        visited[Function::ENTRY] = true;
+        while let Some((block, expanded)) = stack.pop() {
+            if expanded {
+                postorder.push(block);
+                continue;
+            }
+            stack.push((block, true));
+            for successor in self.blocks[block].successors().into_iter().rev() {
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, false));
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /**
+     Drops the blocks control can no longer reach and renumbers the others,
+     keeping their order. Phi arguments of dropped predecessors go as well.
+    */
+    pub fn remove_unreachable(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        for block in self.reverse_postorder() {
+            reachable[block] = true;
+        }
+        let mut renumbered = vec![None; self.blocks.len()];
+        let mut next = 0;
+        for (block, reachable) in reachable.iter().enumerate() {
+            if *reachable {
+                renumbered[block] = Some(next);
+                next += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        for (id, mut block) in blocks.into_iter().enumerate() {
+            if !reachable[id] {
+                continue;
+            }
+            block
+                .terminator
+                .retarget(|target| renumbered[target].expect("successor of a reachable block"));
+            for instr in &mut block.instrs {
+                if let Instr::Phi { args, .. } = instr {
+                    args.retain(|(predecessor, _)| reachable[*predecessor]);
+                    for (predecessor, _) in args.iter_mut() {
+                        *predecessor = renumbered[*predecessor].unwrap();
+                    }
+                }
+            }
+            self.blocks.push(block);
+        }
+        self.prune_phi_args();
+    }
+
+    /**
+     Drops phi arguments of blocks that are no longer predecessors
+    */
+    pub fn prune_phi_args(&mut self) {
+        let predecessors = self.predecessors();
+        for (id, block) in self.blocks.iter_mut().enumerate() {
+            for instr in &mut block.instrs {
+                if let Instr::Phi { args, .. } = instr {
+                    args.retain(|(predecessor, _)| predecessors[id].contains(predecessor));
+                }
+            }
+        }
+    }
+
+    /**
+     Replaces every use of the variables in `replacements`, following chains
+     so a variable replaced by another replaced one ends at the last
+    */
+    pub fn substitute(&mut self, replacements: &HashMap<VarId, Operand>) {
+        let resolve = |operand: &mut Operand| {
+            while let Operand::Var(var) = operand {
+                match replacements.get(var) {
+                    Some(replacement) => *operand = replacement.clone(),
+                    None => break,
+                }
+            }
+        };
+        for block in &mut self.blocks {
+            for instr in &mut block.instrs {
+                for operand in instr.operands_mut() {
+                    resolve(operand);
+                }
+            }
+            if let Some(operand) = block.terminator.operand_mut() {
+                resolve(operand);
+            }
+        }
+    }
+
+    /**
+     Block and position of the instruction defining each variable
+    */
+    pub fn definitions(&self) -> HashMap<VarId, (BlockId, usize)> {
+        let mut definitions = HashMap::new();
+        for (id, block) in self.blocks.iter().enumerate() {
+            for (index, instr) in block.instrs.iter().enumerate() {
+                if let Some(dest) = instr.dest() {
+                    definitions.insert(dest, (id, index));
+                }
+            }
+        }
+        definitions
+    }
+
+    fn operand_text(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Var(var) => self.vars[*var].name.clone(),
+            Operand::Const(value) => value.literal(),
+        }
+    }
+
+    pub fn instr_text(&self, instr: &Instr) -> String {
+        match instr {
+            Instr::Copy { dest, src } => {
+                format!("{} = {}", self.vars[*dest].name, self.operand_text(src))
+            }
+            Instr::Unary { dest, op, operand } => format!(
+                "{} = {op}{}",
+                self.vars[*dest].name,
+                self.operand_text(operand)
+            ),
+            Instr::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => format!(
+                "{} = {} {op} {}",
+                self.vars[*dest].name,
+                self.operand_text(left),
+                self.operand_text(right)
+            ),
+            Instr::Print { value } => format!("print {}", self.operand_text(value)),
+            Instr::Phi { dest, args } => {
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|(predecessor, arg)| {
+                        format!("bb{predecessor}: {}", self.operand_text(arg))
+                    })
+                    .collect();
+                format!("{} = phi [{}]", self.vars[*dest].name, args.join(", "))
+            }
+        }
+    }
+
+    pub fn terminator_text(&self, terminator: &Terminator) -> String {
+        match terminator {
+            Terminator::Jump(target) => format!("jmp bb{target}"),
+            Terminator::Branch {
+                condition,
+                then_block,
+                else_block,
+            } => format!(
+                "br {}, bb{then_block}, bb{else_block}",
+                self.operand_text(condition)
+            ),
+            Terminator::Return => "ret".to_string(),
+        }
+    }
+
+    /**
+     Graphviz DOT of the control-flow graph, one box per basic block with its
+     instructions. Branches label their edges `true` and `false`.
+    */
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph \"{}\" {{\n", self.name);
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            // \l ends a left-aligned line of the label
+            let mut label = format!("bb{id}:\\l");
+            for instr in &block.instrs {
+                label.push_str(&dot_escape(&self.instr_text(instr)));
+                label.push_str("\\l");
+            }
+            label.push_str(&dot_escape(&self.terminator_text(&block.terminator)));
+            label.push_str("\\l");
+            dot.push_str(&format!("    bb{id} [label=\"{label}\"];\n"));
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            match block.terminator {
+                Terminator::Jump(target) => dot.push_str(&format!("    bb{id} -> bb{target};\n")),
+                Terminator::Branch {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    dot.push_str(&format!("    bb{id} -> bb{then_block} [label=\"true\"];\n"));
+                    dot.push_str(&format!(
+                        "    bb{id} -> bb{else_block} [label=\"false\"];\n"
+                    ));
+                }
+                Terminator::Return => {}
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let predecessors = self.predecessors();
+        writeln!(f, "fn {} {{", self.name)?;
+        for (id, block) in self.blocks.iter().enumerate() {
+            if predecessors[id].is_empty() {
+                writeln!(f, "bb{id}:")?;
+            } else {
+                let names: Vec<String> = predecessors[id]
+                    .iter()
+                    .map(|predecessor| format!("bb{predecessor}"))
+                    .collect();
+                writeln!(f, "bb{id}:  ; preds: {}", names.join(", "))?;
+            }
+            for instr in &block.instrs {
+                writeln!(f, "    {}", self.instr_text(instr))?;
+            }
+            writeln!(f, "    {}", self.terminator_text(&block.terminator))?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+struct Builder {
+    function: Function,
+    current: BlockId,
+    scopes: Vec<HashMap<String, VarId>>,
+    temporaries: usize,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.function.blocks.push(Block {
+            instrs: Vec::new(),
+            terminator: Terminator::Return,
+        });
+        self.function.blocks.len() - 1
+    }
+
+    fn push(&mut self, instr: Instr) {
+        self.function.blocks[self.current].instrs.push(instr);
+    }
+
+    /**
+     Ends the current block and continues in `next`
+    */
+    fn terminate(&mut self, terminator: Terminator, next: BlockId) {
+        self.function.blocks[self.current].terminator = terminator;
+        self.current = next;
+    }
+
+    fn temporary(&mut self) -> VarId {
+        let name = format!("%t{}", self.temporaries);
+        self.temporaries += 1;
+        self.function.add_var(name, true)
+    }
+
+    fn declare(&mut self, name: &Ident) -> VarId {
+        let shadowed = self
+            .function
+            .vars
+            .iter()
+            .filter(|var| !var.temporary && var.name.split('.').next() == Some(&name.name))
+            .count();
+        let unique = match shadowed {
+            0 => name.name.clone(),
+            count => format!("{}.{count}", name.name),
+        };
+        let var = self.function.add_var(unique, false);
+        let scope = self.scopes.last_mut().expect("expects a scope");
+        scope.insert(name.name.clone(), var);
+        var
+    }
+
+    fn resolve(&self, name: &Ident) -> VarId {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.name))
+            .copied()
+            .expect("variables are checked by the semantic analysis")
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Operand {
+        match expr {
+            Expr::Literal { value, .. } => Operand::Const(Value::from(value)),
+            Expr::Var(name) => Operand::Var(self.resolve(name)),
+            Expr::Unary { op, operand, .. } => {
+                let operand = self.expr(operand);
+                let dest = self.temporary();
+                self.push(Instr::Unary {
+                    dest,
+                    op: *op,
+                    operand,
+                });
+                Operand::Var(dest)
+            }
+            Expr::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                left,
+                right,
+                ..
+            } => {
+                // the result holds the left operand, replaced by the right
+                // one only when the left does not decide
+                let left = self.expr(left);
+                let dest = self.temporary();
+                self.push(Instr::Copy { dest, src: left });
+                let right_block = self.new_block();
+                let end = self.new_block();
+                let (then_block, else_block) = match op {
+                    BinaryOp::And => (right_block, end),
+                    _ => (end, right_block),
+                };
+                let branch = Terminator::Branch {
+                    condition: Operand::Var(dest),
+                    then_block,
+                    else_block,
+                };
+                self.terminate(branch, right_block);
+                let right = self.expr(right);
+                self.push(Instr::Copy { dest, src: right });
+                self.terminate(Terminator::Jump(end), end);
+                Operand::Var(dest)
+            }
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                let dest = self.temporary();
+                self.push(Instr::Binary {
+                    dest,
+                    op: *op,
+                    left,
+                    right,
+                });
+                Operand::Var(dest)
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Declare { name, ty, .. } => {
+                let dest = self.declare(name);
+                let src = Operand::Const(Value::default_of(*ty));
+                self.push(Instr::Copy { dest, src });
+            }
+            Stmt::Assign { name, value, .. } => {
+                let src = self.expr(value);
+                let dest = self.resolve(name);
+                self.push(Instr::Copy { dest, src });
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.expr(condition);
+                let then_block = self.new_block();
+                let else_block = else_branch.as_ref().map(|_| self.new_block());
+                let join = self.new_block();
+                let branch = Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block: else_block.unwrap_or(join),
+                };
+                self.terminate(branch, then_block);
+                self.stmt(then_branch);
+                if let (Some(else_branch), Some(else_block)) = (else_branch, else_block) {
+                    self.terminate(Terminator::Jump(join), else_block);
+                    self.stmt(else_branch);
+                }
+                self.terminate(Terminator::Jump(join), join);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::Jump(header), header);
+                let condition = self.expr(condition);
+                let branch = Terminator::Branch {
+                    condition,
+                    then_block: body_block,
+                    else_block: exit,
+                };
+                self.terminate(branch, body_block);
+                self.stmt(body);
+                self.terminate(Terminator::Jump(header), exit);
+            }
+            Stmt::Print { value, .. } => {
+                let value = self.expr(value);
+                self.push(Instr::Print { value });
+            }
+            Stmt::Block { body, .. } => {
+                self.scopes.push(HashMap::new());
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.scopes.pop();
+            }
+        }
+    }
+}
+
+/**
+ Lowers a program that passed semantic analysis into the control-flow graph
+ of its `main` function
+*/
+pub fn lower(program: &Program) -> Function {
+    let mut builder = Builder {
+        function: Function {
+            name: "main".to_string(),
+            vars: Vec::new(),
+            blocks: Vec::new(),
+        },
+        current: Function::ENTRY,
+        scopes: vec![HashMap::new()],
+        temporaries: 0,
+    };
+    builder.new_block();
+    for stmt in &program.body {
+        builder.stmt(stmt);
+    }
+    builder.function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::checked;
+
+    fn lower_code(body: &str) -> Function {
+        lower(&checked(&format!("fn main() {{\n{body}\n}}")))
+    }
+
+    fn successors(function: &Function) -> Vec<Vec<BlockId>> {
+        function.blocks.iter().map(Block::successors).collect()
+    }
+
+    #[test]
+    fn if_else_branches_and_joins() {
+        let function = lower_code("x: integer;\nif (x > 0) { x = 1; } else { x = 2; };\nprint(x);");
+        assert_eq!(
+            successors(&function),
+            [vec![1, 2], vec![3], vec![3], vec![]]
+        );
+        assert_eq!(function.predecessors()[3], [1, 2]);
+        assert!(matches!(
+            function.blocks[0].terminator,
+            Terminator::Branch {
+                then_block: 1,
+                else_block: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            function.to_string(),
+            "fn main {
+bb0:
+    x = 0
+    %t0 = x > 0
+    br %t0, bb1, bb2
+bb1:  ; preds: bb0
+    x = 1
+    jmp bb3
+bb2:  ; preds: bb0
+    x = 2
+    jmp bb3
+bb3:  ; preds: bb1, bb2
+    print x
+    ret
+}
+"
+        );
+    }
+
+    #[test]
+    fn while_tests_its_condition_in_a_block_of_its_own() {
+        let function = lower_code("i: integer;\nwhile (i < 3) { i = i + 1; };\nprint(i);");
+        // entry, condition, body looping back to the condition, exit
+        assert_eq!(
+            successors(&function),
+            [vec![1], vec![2, 3], vec![1], vec![]]
+        );
+        assert_eq!(function.predecessors()[1], [0, 2]);
+        assert_eq!(function.reverse_postorder()[..2], [0, 1]);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit_through_blocks() {
+        let function = lower_code("a: boolean; b: boolean;\na = a && b;");
+        let branches = function
+            .blocks
+            .iter()
+            .filter(|block| matches!(block.terminator, Terminator::Branch { .. }))
+            .count();
+        assert_eq!(branches, 1);
+        let exit = function.blocks.len() - 1;
+        assert_eq!(function.predecessors()[exit].len(), 2);
+    }
+
+    #[test]
+    fn dot_export_lists_blocks_and_labelled_edges() {
+        let function = lower_code("i: integer;\nwhile (i < 3) { i = i + 1; };\nprint(i);");
+        assert_eq!(
+            function.to_dot(),
+            r#"digraph "main" {
+    node [shape=box, fontname="monospace"];
+    bb0 [label="bb0:\li = 0\ljmp bb1\l"];
+    bb1 [label="bb1:\l%t0 = i < 3\lbr %t0, bb2, bb3\l"];
+    bb2 [label="bb2:\l%t1 = i + 1\li = %t1\ljmp bb1\l"];
+    bb3 [label="bb3:\lprint i\lret\l"];
+    bb0 -> bb1;
+    bb1 -> bb2 [label="true"];
+    bb1 -> bb3 [label="false"];
+    bb2 -> bb1;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn dot_labels_escape_quotes() {
+        let function = lower_code("s: string;\ns = \"a\\\"b\";\nprint(s);");
+        let dot = function.to_dot();
+        assert!(dot.contains(r#"s = \"a\\\"b\"\l"#), "{dot}");
+    }
+}
@@ -1,73 +1,783 @@
-use regex::Regex;
+use crate::dfa::Dfa;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::OnceLock;
 
+macro_rules! token_kinds {
+    ($($variant:ident => $name:literal,)*) => {
+        /**
+         Kind of a token, serialized with the token name used by the grammar
+        */
+        #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+        pub enum TokenKind {
+            $(#[serde(rename = $name)] $variant,)*
+        }
+
+        impl TokenKind {
+            pub const ALL: &'static [TokenKind] = &[$(TokenKind::$variant,)*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(TokenKind::$variant => $name,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<TokenKind> {
+                match name {
+                    $($name => Some(TokenKind::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+include!(concat!(env!("OUT_DIR"), "/token_kinds.rs"));
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/**
+ Region of the source: 1-based lines and columns (columns count characters,
+ not bytes) plus the byte range `start..end`
+*/
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /**
+     Smallest span covering both spans
+    */
+    pub fn merge(self, other: Span) -> Span {
+        let (first, last) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let end = if last.end >= first.end { last } else { first };
+        Span {
+            start_line: first.start_line,
+            start_column: first.start_column,
+            end_line: end.end_line,
+            end_column: end.end_column,
+            start: first.start,
+            end: end.end,
+        }
+    }
+
+    /**
+     Empty span at the start of this one
+    */
+    pub fn collapse_to_start(self) -> Span {
+        Span {
+            end_line: self.start_line,
+            end_column: self.start_column,
+            end: self.start,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start_line == self.end_line {
+            write!(
+                f,
+                "line {}, column {} to {}",
+                self.start_line, self.start_column, self.end_column
+            )
+        } else {
+            write!(
+                f,
+                "line {}, column {} to line {}, column {}",
+                self.start_line, self.start_column, self.end_line, self.end_column
+            )
+        }
+    }
+}
+
+/**
+ Comment kept as trivia, `kind` is LINE_COMMENT or BLOCK_COMMENT
+*/
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Comment {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/**
+ What went wrong in the text of an ERROR token
+*/
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LexErrorKind {
+    UnterminatedString,
+    InvalidEscape,
+    InvalidUnicodeEscape,
+    UnterminatedComment,
+    UnknownCharacters,
+}
+
+impl LexErrorKind {
+    pub fn message(self, lexeme: &str) -> String {
+        match self {
+            LexErrorKind::UnterminatedString => {
+                "Unterminated string literal, missing closing quote".to_string()
+            }
+            LexErrorKind::InvalidEscape => format!(
+                "Invalid escape sequence '{lexeme}', expected one of \\n \\t \\\\ \\\" \\u{{...}}"
+            ),
+            LexErrorKind::InvalidUnicodeEscape => format!(
+                "Invalid unicode escape '{lexeme}', expected \\u{{...}} with 1 to 6 hex digits of a valid character"
+            ),
+            LexErrorKind::UnterminatedComment => "Unterminated block comment".to_string(),
+            LexErrorKind::UnknownCharacters => format!("Unrecognized characters '{lexeme}'"),
+        }
+    }
+}
+
+/**
+ Token handed to the parser. `literal` is the decoded value of a STRING, its
+ lexeme keeps the quotes and escapes as written. `comments` are the ones
+ written between the previous token and this one. ERROR tokens carry the
+ kind of error in `error`.
+*/
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct Token {
-    pub value: String,
+    pub value: TokenKind,
     pub lexeme: String,
-    pub line: i32,
-    pub initial_position: i32,
-    pub final_position: i32,
+    pub literal: Option<String>,
+    pub span: Span,
+    pub comments: Vec<Comment>,
+    pub error: Option<LexErrorKind>,
 }
 
 impl Token {
-    pub fn new(
-        value: &str,
-        lexeme: &str,
-        line: i32,
-        initial_position: i32,
-        final_position: i32,
-    ) -> Token {
+    pub fn new(value: TokenKind, lexeme: &str, span: Span) -> Token {
         Token {
-            value: value.to_string(),
+            value,
             lexeme: lexeme.to_string(),
-            line,
-            initial_position,
-            final_position,
+            literal: None,
+            span,
+            comments: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn error(kind: LexErrorKind, lexeme: &str, span: Span) -> Token {
+        Token {
+            error: Some(kind),
+            ..Token::new(TokenKind::Error, lexeme, span)
         }
     }
 }
 
+pub const SPEC_PATH: &str = "tokens.txt";
+
+/// the spec is compiled in, build.rs generated `TokenKind` from the same text
+const SPEC: &str = include_str!("../tokens.txt");
+
 /**
- Lexer processes and returns Tokens of a given text
+ One line of the lexical spec
 */
-pub fn tokenize_code(code_text: String) -> Vec<Token> {
-    let pattern = r#"(?P<FN_PROGRAM>\bfn\b)|(?P<MAIN_PROGRAM>\bmain\b)|(?P<INTEGER_TYPE>\binteger\b)|(?P<STRING_TYPE>\bstring\b)|(?P<LESS_THAN_OR_EQUAL><=)|(?P<GREATER_THAN_OR_EQUAL>>=)|(?P<LEFT_PARENTHESIS>\()|(?P<RIGHT_PARENTHESIS>\))|(?P<PLUS>\+)|(?P<LEFT_SQUARE_BRACKET>\[)|(?P<RIGHT_SQUARE_BRACKET>\])|(?P<LEFT_CURLY_BRACE>\{)|(?P<RIGHT_CURLY_BRACE>\})|(?P<STRING>\".*?\")|(?P<MULTIPLY>\*)|(?P<DIVIDE>\/)|(?P<SUBTRACT>\-)|(?P<MODULUS>%)|(?P<EXPONENT>\^)|(?P<GREATER_THAN>>)|(?P<LESS_THAN><)|(?P<EQUAL>=)|(?P<SEMICOLON>;)|(?P<COLON>:)|(?P<IF>\bif\b)|(?P<FOR>\bfor\b)|(?P<WHILE>\bwhile\b)|(?P<PRINT>\bprint\b)|(?P<VARIABLE>[a-zA-Z_]\w*)|(?P<NUMBER>\d+)|"#;
-    let re = Regex::new(pattern).unwrap();
+#[derive(Debug)]
+pub struct TokenRule {
+    pub kind: TokenKind,
+    pub pattern: String,
+    pub priority: i32,
+    pub skip: bool,
+    pub trivia: bool,
+}
 
-    let mut tokens = Vec::new();
+#[derive(Debug)]
+pub struct SpecError {
+    pub message: String,
+    pub line: usize,
+}
 
-    for cap in re.captures_iter(&code_text) {
-        for name in re.capture_names() {
-            if let Some(name) = name {
-                if let Some(matched) = cap.name(name) {
-                    let lexeme = matched.as_str();
-                    let start = matched.start();
-                    let end = matched.end();
-
-                    // Calculate line number
-                    let line = code_text[..start].matches('\n').count() + 1;
-
-                    tokens.push(Token {
-                        value: name.to_string(),
-                        lexeme: lexeme.to_string(),
-                        line: line as i32,
-                        initial_position: start as i32,
-                        final_position: end as i32,
-                    });
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} line {}: {}", SPEC_PATH, self.line, self.message)
+    }
+}
+
+/**
+ Reads `NAME PRIORITY PATTERN [skip|trivia]` lines, ignoring blank lines and
+ `#` comments. Trivia rules are skipped too, their text is kept on the next token.
+*/
+pub fn parse_spec(text: &str) -> Result<Vec<TokenRule>, SpecError> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| SpecError {
+            message,
+            line: number + 1,
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, priority, pattern, flag) = match fields[..] {
+            [name, priority, pattern] => (name, priority, pattern, None),
+            [name, priority, pattern, flag @ ("skip" | "trivia")] => {
+                (name, priority, pattern, Some(flag))
+            }
+            _ => {
+                return Err(error(
+                    "expected NAME PRIORITY PATTERN and an optional skip or trivia".to_string(),
+                ))
+            }
+        };
+        let kind = TokenKind::from_name(name)
+            .ok_or_else(|| error(format!("{name} is not a TokenKind, rebuild to pick it up")))?;
+        if kind == TokenKind::Eof {
+            return Err(error("$ is the end marker and cannot be lexed".to_string()));
+        }
+        let priority = priority
+            .parse()
+            .map_err(|_| error(format!("priority '{priority}' is not a number")))?;
+        rules.push(TokenRule {
+            kind,
+            pattern: pattern.to_string(),
+            priority,
+            skip: flag.is_some(),
+            trivia: flag == Some("trivia"),
+        });
+    }
+    Ok(rules)
+}
+
+struct Scanner {
+    rules: Vec<TokenRule>,
+    dfa: Dfa,
+}
+
+/**
+ Scanner compiled from the spec on first use
+*/
+fn scanner() -> &'static Scanner {
+    static SCANNER: OnceLock<Scanner> = OnceLock::new();
+    SCANNER.get_or_init(|| {
+        let rules = parse_spec(SPEC).unwrap_or_else(|err| panic!("{err}"));
+        let patterns: Vec<(&str, i32)> = rules
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.priority))
+            .collect();
+        let dfa = Dfa::new(&patterns).unwrap_or_else(|err| panic!("{SPEC_PATH}: {err}"));
+        Scanner { rules, dfa }
+    })
+}
+
+pub fn scanner_state_count() -> usize {
+    scanner().dfa.state_count()
+}
+
+/**
+ Names of every token the lexer hands to the parser, including the end marker `$`
+*/
+pub fn token_names() -> Vec<String> {
+    let mut names: Vec<String> = scanner()
+        .rules
+        .iter()
+        .filter(|rule| !rule.skip && rule.kind != TokenKind::Error)
+        .map(|rule| rule.kind.name().to_string())
+        .collect();
+    names.push(TokenKind::Eof.name().to_string());
+    names
+}
+
+/**
+ Lexical diagnostic built from an ERROR token
+*/
+#[derive(Serialize, Debug)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub message: String,
+    pub span: Span,
+}
+
+pub fn lexical_errors(tokens: &[Token]) -> Vec<LexError> {
+    tokens
+        .iter()
+        .filter(|token| token.value == TokenKind::Error)
+        .map(|token| {
+            let kind = token.error.unwrap_or(LexErrorKind::UnknownCharacters);
+            LexError {
+                kind,
+                message: kind.message(&token.lexeme),
+                span: token.span,
+            }
+        })
+        .collect()
+}
+
+/**
+ Value of a NUMBER lexeme in decimal, `0x` hex or `0b` binary with `_`
+ separators, None when it does not fit in 64 bits
+*/
+pub fn integer_literal(lexeme: &str) -> Option<u64> {
+    let digits = lexeme.replace('_', "");
+    if let Some(hex) = digits.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        u64::from_str_radix(binary, 2).ok()
+    } else {
+        digits.parse().ok()
+    }
+}
+
+/**
+ Value of a FLOAT lexeme
+*/
+pub fn float_literal(lexeme: &str) -> Option<f64> {
+    lexeme.replace('_', "").parse().ok()
+}
+
+/**
+ Turns increasing byte offsets into line/column positions, walking the text once
+*/
+struct Locator<'a> {
+    text: &'a str,
+    offset: usize,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Locator<'a> {
+    fn new(text: &'a str) -> Locator<'a> {
+        Locator {
+            text,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn advance_to(&mut self, offset: usize) -> (u32, u32) {
+        for character in self.text[self.offset..offset].chars() {
+            if character == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.offset = offset;
+        (self.line, self.column)
+    }
+
+    fn span(&mut self, start: usize, end: usize) -> Span {
+        let (start_line, start_column) = self.advance_to(start);
+        let (end_line, end_column) = self.advance_to(end);
+        Span {
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+            start,
+            end,
+        }
+    }
+}
+
+/**
+ String literal read by `scan_string`: its end, decoded value and the byte
+ ranges of escapes that could not be decoded, with what is wrong with them
+*/
+struct ScannedString {
+    end: usize,
+    value: String,
+    terminated: bool,
+    bad_escapes: Vec<(usize, usize, LexErrorKind)>,
+}
+
+/**
+ Reads the string opening at `start` up to its closing quote, decoding
+ `\n \t \\ \" \u{...}`. Strings may span lines, an invalid escape is kept
+ as written in the value.
+*/
+fn scan_string(code_text: &str, start: usize) -> ScannedString {
+    let mut scanned = ScannedString {
+        end: code_text.len(),
+        value: String::new(),
+        terminated: false,
+        bad_escapes: Vec::new(),
+    };
+    let mut chars = code_text[start + 1..].char_indices().peekable();
+    while let Some((offset, character)) = chars.next() {
+        let position = start + 1 + offset;
+        match character {
+            '"' => {
+                scanned.end = position + 1;
+                scanned.terminated = true;
+                break;
+            }
+            '\\' => {
+                let mut kind = LexErrorKind::InvalidEscape;
+                let escape = match chars.next() {
+                    Some((_, 'n')) => Some('\n'),
+                    Some((_, 't')) => Some('\t'),
+                    Some((_, '\\')) => Some('\\'),
+                    Some((_, '"')) => Some('"'),
+                    Some((_, 'u')) => {
+                        kind = LexErrorKind::InvalidUnicodeEscape;
+                        let mut digits = String::new();
+                        let mut closed = false;
+                        if chars.next_if(|(_, next)| *next == '{').is_some() {
+                            while let Some((_, next)) = chars.next_if(|(_, next)| *next != '"') {
+                                if next == '}' {
+                                    closed = true;
+                                    break;
+                                }
+                                digits.push(next);
+                            }
+                        }
+                        if closed && (1..=6).contains(&digits.len()) {
+                            u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+                let escape_end = match chars.peek() {
+                    Some((offset, _)) => start + 1 + offset,
+                    None => code_text.len(),
+                };
+                match escape {
+                    Some(decoded) => scanned.value.push(decoded),
+                    None => {
+                        scanned.value.push_str(&code_text[position..escape_end]);
+                        scanned.bad_escapes.push((position, escape_end, kind));
+                    }
+                }
+            }
+            _ => scanned.value.push(character),
+        }
+    }
+    scanned
+}
+
+/**
+ End of the block comment opening at `start`, just past the close that
+ matches its opening when comments are nested. None when the text ends first.
+*/
+fn block_comment_end(code_text: &str, start: usize) -> Option<usize> {
+    let bytes = code_text.as_bytes();
+    let mut depth = 0;
+    let mut position = start;
+    while position + 1 < bytes.len() {
+        match (bytes[position], bytes[position + 1]) {
+            (b'/', b'*') => {
+                depth += 1;
+                position += 2;
+            }
+            (b'*', b'/') => {
+                depth -= 1;
+                position += 2;
+                if depth == 0 {
+                    return Some(position);
                 }
             }
+            _ => position += 1,
         }
     }
+    None
+}
+
+/**
+ Scans the whole text in one pass with the longest match at each position.
+ Text no rule matches is gathered into an ERROR token up to the next match,
+ tokens of skip rules are dropped and trivia is attached to the next token.
+ BLOCK_COMMENT and STRING matches are only their opening, the scan extends
+ them to the matching close and an unterminated one becomes an ERROR token
+ running to the end. Bad escapes in a string are ERROR tokens after it.
+*/
+pub fn scan(code_text: &str) -> Vec<Token> {
+    let scanner = scanner();
+    let bytes = code_text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut locator = Locator::new(code_text);
+    let mut unmatched_start: Option<usize> = None;
+    let mut comments: Vec<Comment> = Vec::new();
+    let mut position = 0;
+
+    while let Some(character) = code_text[position..].chars().next() {
+        let (rule, end) = match scanner.dfa.longest_match(bytes, position) {
+            Some(matched) => matched,
+            None => {
+                unmatched_start.get_or_insert(position);
+                position += character.len_utf8();
+                continue;
+            }
+        };
+        if let Some(start) = unmatched_start.take() {
+            let span = locator.span(start, position);
+            tokens.push(Token::error(
+                LexErrorKind::UnknownCharacters,
+                &code_text[start..position],
+                span,
+            ));
+        }
+        let rule = &scanner.rules[rule];
+        if rule.kind == TokenKind::String {
+            let scanned = scan_string(code_text, position);
+            let lexeme = &code_text[position..scanned.end];
+            let (start_line, start_column) = locator.advance_to(position);
+            let escape_errors: Vec<Token> = scanned
+                .bad_escapes
+                .iter()
+                .map(|(start, end, kind)| {
+                    Token::error(*kind, &code_text[*start..*end], locator.span(*start, *end))
+                })
+                .collect();
+            let (end_line, end_column) = locator.advance_to(scanned.end);
+            let span = Span {
+                start_line,
+                start_column,
+                end_line,
+                end_column,
+                start: position,
+                end: scanned.end,
+            };
+            let mut token = if scanned.terminated {
+                let mut token = Token::new(TokenKind::String, lexeme, span);
+                token.literal = Some(scanned.value);
+                token
+            } else {
+                Token::error(LexErrorKind::UnterminatedString, lexeme, span)
+            };
+            token.comments = std::mem::take(&mut comments);
+            tokens.push(token);
+            tokens.extend(escape_errors);
+            position = scanned.end;
+            continue;
+        }
+        let (error, end) = match rule.kind {
+            TokenKind::BlockComment => match block_comment_end(code_text, position) {
+                Some(end) => (None, end),
+                None => (Some(LexErrorKind::UnterminatedComment), code_text.len()),
+            },
+            _ => (None, end),
+        };
+        let lexeme = &code_text[position..end];
+        if let Some(error) = error {
+            let mut token = Token::error(error, lexeme, locator.span(position, end));
+            token.comments = std::mem::take(&mut comments);
+            tokens.push(token);
+        } else if !rule.skip {
+            let mut token = Token::new(rule.kind, lexeme, locator.span(position, end));
+            token.comments = std::mem::take(&mut comments);
+            tokens.push(token);
+        } else if rule.trivia {
+            comments.push(Comment {
+                kind: rule.kind,
+                text: lexeme.to_string(),
+                span: locator.span(position, end),
+            });
+        }
+        position = end;
+    }
+    if let Some(start) = unmatched_start {
+        let span = locator.span(start, position);
+        tokens.push(Token::error(
+            LexErrorKind::UnknownCharacters,
+            &code_text[start..position],
+            span,
+        ));
+    }
+
+    // the end marker sits just past the last character so errors on it point somewhere useful
+    let end = code_text.len();
+    let mut eof = Token::new(TokenKind::Eof, "$", locator.span(end, end));
+    eof.comments = comments;
+    tokens.push(eof);
+    tokens
+}
+
+/**
+ Lexer processes and returns Tokens of a given text
+*/
+pub fn tokenize_code(code_text: String) -> Vec<Token> {
+    let tokens = scan(&code_text);
 
     // Print tokens for debugging
-    for token in &tokens {
-        println!(
-            "{}: '{}' [{}-{}] on line {}",
-            token.value, token.lexeme, token.initial_position, token.final_position, token.line
+    for token in &tokens[..tokens.len() - 1] {
+        println!("{}: '{}' [{}]", token.value, token.lexeme, token.span);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(tokens: &[Token]) -> Vec<TokenKind> {
+        tokens.iter().map(|token| token.value).collect()
+    }
+
+    fn error_kinds(code: &str) -> Vec<LexErrorKind> {
+        lexical_errors(&scan(code))
+            .iter()
+            .map(|err| err.kind)
+            .collect()
+    }
+
+    #[test]
+    fn keywords_and_operators_take_the_longest_match() {
+        let tokens = scan("if iffy while_x <= <\n  integer1 ==");
+        assert_eq!(
+            kinds(&tokens),
+            [
+                TokenKind::If,
+                TokenKind::Variable,
+                TokenKind::Variable,
+                TokenKind::LessThanOrEqual,
+                TokenKind::LessThan,
+                TokenKind::Variable,
+                TokenKind::EqualEqual,
+                TokenKind::Eof
+            ]
         );
+        let integer = &tokens[5].span;
+        assert_eq!((integer.start_line, integer.start_column), (2, 3));
+        assert_eq!((integer.end_line, integer.end_column), (2, 11));
+        assert_eq!(tokens[7].span.start, 34);
     }
 
-    tokens.push(Token::new("$", "$", 0, 0, 0));
-    tokens
+    #[test]
+    fn spec_lines_are_checked() {
+        let rules = parse_spec("# comment\n\nWHITESPACE 0 \\s+ skip\nIF 2 if\n").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].skip && !rules[0].trivia);
+        assert_eq!((rules[1].kind, rules[1].priority), (TokenKind::If, 2));
+
+        let line = |text: &str| parse_spec(text).unwrap_err().line;
+        assert_eq!(line("IF 2 if\nNOT_A_TOKEN 1 x"), 2);
+        assert_eq!(line("IF high if"), 1);
+        assert_eq!(line("IF 2 if keep"), 1);
+        assert_eq!(line("$ 1 x"), 1);
+    }
+
+    #[test]
+    fn token_names_are_the_tokens_the_parser_sees() {
+        let names = token_names();
+        assert!(names.iter().any(|name| name == "EQUAL_EQUAL"));
+        assert_eq!(names.last().map(String::as_str), Some("$"));
+        for hidden in ["WHITESPACE", "LINE_COMMENT", "BLOCK_COMMENT", "ERROR"] {
+            assert!(!names.iter().any(|name| name == hidden), "{hidden}");
+        }
+        let table = crate::table::read_table(crate::table::TABLE_PATH).unwrap();
+        let errors = crate::table::validate_table(&table, &names);
+        assert!(errors.is_empty(), "{}", errors[0]);
+    }
+
+    #[test]
+    fn comments_are_trivia_of_the_next_token() {
+        let tokens = scan("x // note\n/* a /* nested */ b */ = 1; // last");
+        assert_eq!(
+            kinds(&tokens),
+            [
+                TokenKind::Variable,
+                TokenKind::Equal,
+                TokenKind::Number,
+                TokenKind::Semicolon,
+                TokenKind::Eof
+            ]
+        );
+        let comments = &tokens[1].comments;
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].kind, TokenKind::LineComment);
+        assert_eq!(comments[0].text, "// note");
+        assert_eq!(comments[1].kind, TokenKind::BlockComment);
+        assert_eq!(comments[1].text, "/* a /* nested */ b */");
+        assert_eq!(comments[1].span.start_line, 2);
+        // comments after the last token stay on the end marker
+        assert_eq!(tokens[4].comments[0].text, "// last");
+        assert!(tokens[0].comments.is_empty());
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_an_error_to_the_end() {
+        let tokens = scan("x = 1;\n/* open /* nested */\ny = 2;");
+        let error = &tokens[4];
+        assert_eq!(error.error, Some(LexErrorKind::UnterminatedComment));
+        assert_eq!(error.lexeme, "/* open /* nested */\ny = 2;");
+        assert_eq!((error.span.start_line, error.span.start_column), (2, 1));
+        assert_eq!((error.span.end_line, error.span.end_column), (3, 7));
+        assert_eq!(kinds(&tokens[5..]), [TokenKind::Eof]);
+        assert_eq!(
+            lexical_errors(&tokens)[0].message,
+            "Unterminated block comment"
+        );
+    }
+
+    #[test]
+    fn valid_escapes_are_decoded() {
+        let tokens = scan(r#""a\nb\t\\\"\u{48}\u{1F600}""#);
+        assert_eq!(kinds(&tokens), [TokenKind::String, TokenKind::Eof]);
+        assert_eq!(tokens[0].literal.as_deref(), Some("a\nb\t\\\"H\u{1F600}"));
+        assert!(lexical_errors(&tokens).is_empty());
+    }
+
+    #[test]
+    fn invalid_escapes_are_reported_after_the_string() {
+        let tokens = scan(r#""a\qb\u{110000}\u{}""#);
+        assert_eq!(
+            kinds(&tokens),
+            [
+                TokenKind::String,
+                TokenKind::Error,
+                TokenKind::Error,
+                TokenKind::Error,
+                TokenKind::Eof
+            ]
+        );
+        // the string is still usable, keeping the bad escapes as written
+        assert_eq!(tokens[0].literal.as_deref(), Some(r"a\qb\u{110000}\u{}"));
+        let errors = lexical_errors(&tokens);
+        assert_eq!(errors[0].kind, LexErrorKind::InvalidEscape);
+        assert_eq!(errors[0].span.start..errors[0].span.end, 2..4);
+        assert!(errors[0]
+            .message
+            .starts_with(r"Invalid escape sequence '\q'"));
+        assert_eq!(errors[1].kind, LexErrorKind::InvalidUnicodeEscape);
+        assert!(errors[1]
+            .message
+            .starts_with(r"Invalid unicode escape '\u{110000}'"));
+        assert_eq!(errors[2].kind, LexErrorKind::InvalidUnicodeEscape);
+    }
+
+    #[test]
+    fn unterminated_string_runs_to_the_end() {
+        let tokens = scan("x = \"open\nline");
+        let error = &tokens[2];
+        assert_eq!(error.value, TokenKind::Error);
+        assert_eq!(error.error, Some(LexErrorKind::UnterminatedString));
+        assert_eq!(error.lexeme, "\"open\nline");
+        assert_eq!((error.span.end_line, error.span.end_column), (2, 5));
+        assert_eq!(error_kinds("\"a\\\""), [LexErrorKind::UnterminatedString]);
+    }
+
+    #[test]
+    fn stray_backslash_is_an_unknown_character() {
+        let errors = lexical_errors(&scan(r"x = \u;"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnknownCharacters);
+        assert_eq!(errors[0].message, r"Unrecognized characters '\'");
+        assert_eq!(errors[0].span.start_column, 5);
+    }
 }
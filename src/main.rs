@@ -1,19 +1,43 @@
 use axum::{
-    extract::Json,
+    extract::{Extension, Json},
     http::Response,
     response::IntoResponse,
     routing::{get, post},
-    Router,
+    AddExtensionLayer, Router,
 };
 use serde::Deserialize;
+use serde_json::json;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Instant;
 use tera::{Context, Tera};
 
+mod ast;
+mod codegen;
+mod dfa;
+mod grammar;
+mod interpreter;
+mod ir;
 mod lexer;
+mod opt;
 mod parser;
 mod semantic;
+mod ssa;
+mod table;
+#[cfg(test)]
+mod test_support;
 mod util;
+mod vm;
+
+/**
+ Syntax table, templates and execution limits loaded once at startup and
+ shared by every handler
+*/
+struct AppState {
+    table: table::ParseTable,
+    tera: Tera,
+    limits: interpreter::Limits,
+}
 
 #[derive(Deserialize)]
 struct TokenRequest {
@@ -21,60 +45,208 @@ struct TokenRequest {
     mode: String,
 }
 
-async fn check_language(Json(body): Json<TokenRequest>) -> impl IntoResponse {
+async fn check_language(
+    Json(body): Json<TokenRequest>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
     let start_time = Instant::now();
     let tokens = lexer::tokenize_code(body.code_text.to_string());
     let tokens_processed = tokens.len();
-    let (is_syntax_correct, syntax_tree) = parser::syntax_parse(tokens);
+    let lexical_errors = lexer::lexical_errors(&tokens);
+    let syntax_result = parser::syntax_parse(tokens, &state.table);
+    let is_syntax_correct = syntax_result.is_ok();
     println!("{}", is_syntax_correct);
 
+    // the tree is partial when there are syntax errors, the healthy commands are still checked
     let mut semantic_error = None;
-    if is_syntax_correct {
-        if let Some(syntax_tree) = syntax_tree {
-            if let Err(err) = semantic::semantic_analysis(&syntax_tree) {
-                semantic_error = Some(format!(
-                    "Semantic Error: {} at line {}, position {} to {}",
-                    err.message, err.line, err.initial_position, err.final_position
-                ));
-            }
-        }
+    let checked = ast::lower(&syntax_result.syntax_tree)
+        .and_then(|program| semantic::semantic_analysis(&program));
+    if let Err(err) = checked {
+        semantic_error = Some(format!("Semantic Error: {} at {}", err.message, err.span));
     }
+    let syntax_errors = syntax_result.errors;
     let end_time = Instant::now();
     let elapsed_time = (end_time - start_time).as_secs_f64() * 1000.0;
     println!(
         "INFO: Processed and analysed syntax in tokens -> {tokens_processed} in {elapsed_time} ms"
     );
-    let tera = Tera::new("templates/**/*").unwrap();
+    if body.mode == "json" {
+        return Json(json!({
+            "lexical_errors": lexical_errors,
+            "is_syntax_correct": is_syntax_correct,
+            "syntax_errors": syntax_errors,
+            "semantic_error": semantic_error,
+        }))
+        .into_response();
+    }
+
     let mut context = Context::new();
+    let lexical_errors: Vec<String> = lexical_errors
+        .iter()
+        .map(|err| format!("Lexical Error: {} at {}", err.message, err.span))
+        .collect();
+    context.insert("lexical_errors", &lexical_errors);
     context.insert("is_syntax_correct", &is_syntax_correct);
+    let syntax_errors: Vec<String> = syntax_errors
+        .iter()
+        .map(|error| format!("Syntax Error: {error}"))
+        .collect();
+    context.insert("syntax_errors", &syntax_errors);
     if let Some(error) = semantic_error {
         context.insert("semantic_error", &error);
     }
     context.insert("code_text", &body.code_text);
 
-    let rendered = tera.render("compiler_template.html", &context).unwrap();
+    let rendered = state
+        .tera
+        .render("compiler_template.html", &context)
+        .unwrap();
+    rendered.into_response()
+}
+
+/**
+ Runs every phase up to semantic analysis. The program is only returned when
+ no phase found an error, otherwise every error is returned already formatted.
+*/
+fn check_program(
+    tokens: Vec<lexer::Token>,
+    table: &table::ParseTable,
+) -> Result<ast::Program, Vec<String>> {
+    let mut errors: Vec<String> = lexer::lexical_errors(&tokens)
+        .iter()
+        .map(|err| format!("Lexical Error: {} at {}", err.message, err.span))
+        .collect();
+    let syntax_result = parser::syntax_parse(tokens, table);
+    errors.extend(
+        syntax_result
+            .errors
+            .iter()
+            .map(|error| format!("Syntax Error: {error}")),
+    );
+    let checked = ast::lower(&syntax_result.syntax_tree)
+        .and_then(|program| semantic::semantic_analysis(&program).map(|()| program));
+    match checked {
+        Ok(program) if errors.is_empty() => Ok(program),
+        Ok(_) => Err(errors),
+        Err(err) => {
+            errors.push(format!("Semantic Error: {} at {}", err.message, err.span));
+            Err(errors)
+        }
+    }
+}
+
+async fn run_code(
+    Json(body): Json<TokenRequest>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let start_time = Instant::now();
+    let tokens = lexer::tokenize_code(body.code_text.to_string());
+    let (errors, execution) = match check_program(tokens, &state.table) {
+        Ok(program) => (
+            Vec::new(),
+            Some(interpreter::run_with_timeout(program, state.limits).await),
+        ),
+        Err(errors) => (errors, None),
+    };
+    let end_time = Instant::now();
+    let elapsed_time = (end_time - start_time).as_secs_f64() * 1000.0;
+    println!(" INFO: checked and ran the program in {elapsed_time} ms");
+
+    if body.mode == "json" {
+        return Json(json!({
+            "errors": errors,
+            "output": execution.as_ref().map(|execution| &execution.output),
+            "runtime_error": execution.as_ref().and_then(|execution| execution.error.as_ref()),
+        }))
+        .into_response();
+    }
+
+    let mut context = Context::new();
+    context.insert("errors", &errors);
+    if let Some(execution) = execution {
+        context.insert("output", &execution.output);
+        if let Some(err) = execution.error {
+            let error = format!("Runtime Error: {} at {}", err.message, err.span);
+            context.insert("runtime_error", &error);
+        }
+    }
+    context.insert("code_text", &body.code_text);
+    let rendered = state.tera.render("run_template.html", &context).unwrap();
+    rendered.into_response()
+}
+
+async fn show_bytecode(
+    Json(body): Json<TokenRequest>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let tokens = lexer::tokenize_code(body.code_text.to_string());
+    let (errors, listing) = match check_program(tokens, &state.table) {
+        Ok(program) => (
+            Vec::new(),
+            codegen::disassemble(&codegen::compile(&program)),
+        ),
+        Err(errors) => (errors, String::new()),
+    };
+
+    if body.mode == "json" {
+        return Json(json!({
+            "errors": errors,
+            "source": body.code_text,
+            "listing": listing,
+        }))
+        .into_response();
+    }
+
+    let mut context = Context::new();
+    context.insert("errors", &errors);
+    context.insert("listing", &listing);
+    context.insert("code_text", &body.code_text);
+    let rendered = state
+        .tera
+        .render("bytecode_template.html", &context)
+        .unwrap();
     rendered.into_response()
 }
 
-async fn check_syntax(Json(body): Json<TokenRequest>) -> impl IntoResponse {
+async fn check_syntax(
+    Json(body): Json<TokenRequest>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
     let start_time = Instant::now();
     let tokens = lexer::tokenize_code(body.code_text.to_string());
     let tokens_processed = tokens.len();
-    let (is_syntax_correct, _) = parser::syntax_parse(tokens);
+    let syntax_result = parser::syntax_parse(tokens, &state.table);
+    let is_syntax_correct = syntax_result.is_ok();
+    let syntax_errors = syntax_result.errors;
     let end_time = Instant::now();
     let elapsed_time = (end_time - start_time).as_secs_f64() * 1000.0;
     println!(
         " INFO: processed and analysed syntax in tokens -> {tokens_processed} in {elapsed_time} ms"
     );
 
-    let tera = Tera::new("templates/**/*").unwrap();
+    if body.mode == "json" {
+        return Json(json!({
+            "is_syntax_correct": is_syntax_correct,
+            "syntax_errors": syntax_errors,
+        }))
+        .into_response();
+    }
+
     let mut context = tera::Context::new();
     context.insert("is_syntax_correct", &is_syntax_correct);
-    let rendered = tera.render("syntax_template.html", &context).unwrap();
+    let syntax_errors: Vec<String> = syntax_errors
+        .iter()
+        .map(|error| format!("Syntax Error: {error}"))
+        .collect();
+    context.insert("syntax_errors", &syntax_errors);
+    let rendered = state.tera.render("syntax_template.html", &context).unwrap();
     rendered.into_response()
 }
 
-async fn generate_tokens(Json(body): Json<TokenRequest>) -> impl IntoResponse {
+async fn generate_tokens(
+    Json(body): Json<TokenRequest>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
     let start_time = Instant::now();
     let tokens = lexer::tokenize_code(body.code_text.to_string());
     let tokens_processed = tokens.len();
@@ -82,10 +254,9 @@ async fn generate_tokens(Json(body): Json<TokenRequest>) -> impl IntoResponse {
     let elapsed_time = (end_time - start_time).as_secs_f64() * 1000.0;
     println!(" INFO: processed tokens -> {tokens_processed} in {elapsed_time} ms");
 
-    let tera = Tera::new("templates/**/*").unwrap();
     let mut context = tera::Context::new();
     context.insert("tokens", &tokens);
-    let rendered = tera.render("tokens_template.html", &context).unwrap();
+    let rendered = state.tera.render("tokens_template.html", &context).unwrap();
 
     match body.mode.as_str() {
         "json" => Json(tokens).into_response(),
@@ -94,44 +265,346 @@ async fn generate_tokens(Json(body): Json<TokenRequest>) -> impl IntoResponse {
     }
 }
 
-async fn index() -> impl IntoResponse {
-    let tera = Tera::new("templates/**/*").unwrap();
+async fn index(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
     let context = Context::new();
-    let rendered = tera.render("index_template.html", &context).unwrap();
+    let rendered = state.tera.render("index_template.html", &context).unwrap();
     Response::new(rendered)
 }
 
-async fn tab1() -> impl IntoResponse {
-    let tera = Tera::new("templates/**/*").unwrap();
+async fn tab1(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
     let context = Context::new();
-    let rendered = tera.render("tab1.html", &context).unwrap();
+    let rendered = state.tera.render("tab1.html", &context).unwrap();
     Response::new(rendered)
 }
 
-async fn tab2() -> impl IntoResponse {
-    let tera = Tera::new("templates/**/*").unwrap();
+async fn tab2(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
     let context = Context::new();
-    let rendered = tera.render("tab2.html", &context).unwrap();
+    let rendered = state.tera.render("tab2.html", &context).unwrap();
     Response::new(rendered)
 }
 
-async fn tab3() -> impl IntoResponse {
-    let tera = Tera::new("templates/**/*").unwrap();
+async fn tab3(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
     let context = Context::new();
-    let rendered = tera.render("tab3.html", &context).unwrap();
+    let rendered = state.tera.render("tab3.html", &context).unwrap();
     Response::new(rendered)
 }
 
-#[tokio::main]
-async fn main() {
+async fn tab4(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    let context = Context::new();
+    let rendered = state.tera.render("tab4.html", &context).unwrap();
+    Response::new(rendered)
+}
+
+async fn tab5(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    let context = Context::new();
+    let rendered = state.tera.render("tab5.html", &context).unwrap();
+    Response::new(rendered)
+}
+
+/**
+ Reads a grammar and checks every terminal it uses is a token of tokens.txt
+*/
+fn load_grammar(grammar_path: &str) -> grammar::Grammar {
+    let grammar = match grammar::parse_grammar(&util::read_file(grammar_path)) {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            eprintln!("Grammar Error: {} at line {}", err.message, err.line);
+            std::process::exit(1);
+        }
+    };
+    let token_names = lexer::token_names();
+    let unknown: Vec<&String> = grammar
+        .terminals
+        .iter()
+        .filter(|terminal| !token_names.contains(terminal))
+        .collect();
+    for terminal in &unknown {
+        eprintln!(
+            "Grammar Error: terminal {terminal} is not a token of {}",
+            lexer::SPEC_PATH
+        );
+    }
+    if !unknown.is_empty() {
+        std::process::exit(1);
+    }
+    grammar
+}
+
+/**
+ Builds the parse table from a grammar file and writes it as syntax_table.json.
+ `--mode slr|lalr|lr1` selects the construction, SLR by default.
+*/
+fn generate_table(args: &[String]) {
+    let mut mode = grammar::TableMode::Slr;
+    let mut paths: Vec<&str> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--mode" {
+            let name = args.next().map(String::as_str).unwrap_or("");
+            mode = match grammar::TableMode::from_name(name) {
+                Some(mode) => mode,
+                None => {
+                    eprintln!("Unknown table mode '{name}', expected slr, lalr or lr1");
+                    std::process::exit(1);
+                }
+            };
+        } else {
+            paths.push(arg);
+        }
+    }
+    let grammar_path = paths.first().copied().unwrap_or("grammar.txt");
+    let table_path = paths.get(1).copied().unwrap_or("syntax_table.json");
+
+    let grammar = load_grammar(grammar_path);
+    let table = grammar.build_table(mode);
+    if table.resolved > 0 {
+        println!(
+            "INFO: {} conflicts resolved by precedence declarations",
+            table.resolved
+        );
+    }
+    if !table.conflicts.is_empty() {
+        eprint!("{}", table.conflict_report(&grammar));
+        eprintln!(
+            "Grammar is not {mode}: {} conflicts, {table_path} was not written",
+            table.conflicts.len()
+        );
+        std::process::exit(1);
+    }
+
+    let json = serde_json::to_string_pretty(&table.to_json(&grammar)).unwrap();
+    std::fs::write(table_path, json).expect("Error writing file");
+    println!(
+        "INFO: generated {} {mode} states from {grammar_path} into {table_path}",
+        table.action.len()
+    );
+}
+
+/**
+ Builds the table in every mode and prints state and conflict counts side by side
+*/
+fn compare_tables(args: &[String]) {
+    let grammar_path = args.first().map(String::as_str).unwrap_or("grammar.txt");
+    let grammar = load_grammar(grammar_path);
+
+    println!(
+        "{:<10}{:>8}{:>12}{:>12}",
+        "mode", "states", "conflicts", "resolved"
+    );
+    for mode in grammar::TableMode::ALL {
+        let table = grammar.build_table(mode);
+        println!(
+            "{:<10}{:>8}{:>12}{:>12}",
+            mode.to_string(),
+            table.action.len(),
+            table.conflicts.len(),
+            table.resolved
+        );
+    }
+    for mode in grammar::TableMode::ALL {
+        let table = grammar.build_table(mode);
+        if !table.conflicts.is_empty() {
+            println!("\n{mode} conflicts:");
+            print!("{}", table.conflict_report(&grammar));
+        }
+    }
+}
+
+/**
+ Validates a syntax table against the lexer's tokens, printing every problem found
+*/
+fn check_table(table_path: &str) -> bool {
+    let table = match table::read_table(table_path) {
+        Ok(table) => table,
+        Err(err) => {
+            eprintln!("Table Error: {err}");
+            return false;
+        }
+    };
+    let errors = table::validate_table(&table, &lexer::token_names());
+    for error in &errors {
+        eprintln!("Table Error: {error}");
+    }
+    if !errors.is_empty() {
+        return false;
+    }
+    // a well formed table can still reduce past the bottom of the stack or miss a GOTO
+    match table::ParseTable::load(table_path) {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("Table Error: {err}");
+            false
+        }
+    }
+}
+
+fn validate_table(args: &[String]) {
+    let table_path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or(table::TABLE_PATH);
+    if !check_table(table_path) {
+        std::process::exit(1);
+    }
+    println!("INFO: {table_path} is valid");
+}
+
+/**
+ Times the scanner on inputs of growing size built by repeating a source file,
+ linear scanning keeps the MB/s column flat
+*/
+fn bench_lexer(args: &[String]) {
+    let source_path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("example_code.txt");
+    let source = util::read_file(source_path);
+    println!(
+        "INFO: scanner has {} DFA states",
+        lexer::scanner_state_count()
+    );
+    println!("{:>8}{:>12}{:>12}{:>12}", "MB", "tokens", "ms", "MB/s");
+    for megabytes in [1, 2, 4, 8, 16] {
+        let size = megabytes * 1024 * 1024;
+        let mut code_text = String::with_capacity(size + source.len());
+        while code_text.len() < size {
+            code_text.push_str(&source);
+            code_text.push('\n');
+        }
+        let start_time = Instant::now();
+        let tokens = lexer::scan(&code_text);
+        let elapsed = start_time.elapsed().as_secs_f64();
+        println!(
+            "{:>8}{:>12}{:>12.1}{:>12.1}",
+            megabytes,
+            tokens.len(),
+            elapsed * 1000.0,
+            code_text.len() as f64 / (1024.0 * 1024.0) / elapsed
+        );
+    }
+}
+
+fn load_limits() -> interpreter::Limits {
+    interpreter::Limits::from_env().unwrap_or_else(|err| {
+        eprintln!("Limits Error: {err}");
+        std::process::exit(1);
+    })
+}
+
+/**
+ Checks a source file without tracing, printing its errors to stderr and
+ exiting with status 1 if it has any
+*/
+fn check_file(source_path: &str) -> ast::Program {
+    util::set_trace(false);
+    let table = table::ParseTable::load(table::TABLE_PATH).unwrap_or_else(|err| {
+        eprintln!("Table Error: {err}");
+        std::process::exit(1);
+    });
+    let tokens = lexer::scan(&util::read_file(source_path));
+    check_program(tokens, &table).unwrap_or_else(|errors| {
+        for error in errors {
+            eprintln!("{error}");
+        }
+        std::process::exit(1);
+    })
+}
+
+/**
+ Checks and runs a source file, printing only what the program prints.
+ `--vm` runs it on the bytecode VM instead of the tree interpreter.
+ Errors go to stderr and exit with status 1.
+*/
+async fn run_file(args: &[String]) {
+    let use_vm = args.iter().any(|arg| arg == "--vm");
+    let source_path = args
+        .iter()
+        .find(|arg| *arg != "--vm")
+        .map(String::as_str)
+        .unwrap_or("example_code.txt");
+    let limits = load_limits();
+    let program = check_file(source_path);
+    let execution = if use_vm {
+        vm::run_with_timeout(codegen::compile(&program), limits).await
+    } else {
+        interpreter::run_with_timeout(program, limits).await
+    };
+    print!("{}", execution.output);
+    if let Some(err) = execution.error {
+        eprintln!("Runtime Error: {} at {}", err.message, err.span);
+        std::process::exit(1);
+    }
+}
+
+/**
+ Prints the three-address code of a source file, or with `--dot` its
+ control-flow graph in Graphviz DOT. `-O1` and `-O2` optimize it, printing
+ the code after each pass unless `--dot` asks for the final graph only.
+*/
+fn show_ir(args: &[String]) {
+    let mut dot = false;
+    let mut level = 0;
+    let mut source_path = "example_code.txt";
+    for arg in args {
+        match arg.as_str() {
+            "--dot" => dot = true,
+            "-O0" => level = 0,
+            "-O1" => level = 1,
+            "-O2" => level = 2,
+            option if option.starts_with("-O") => {
+                eprintln!("Unknown optimization level '{option}', expected -O0, -O1 or -O2");
+                std::process::exit(1);
+            }
+            path => source_path = path,
+        }
+    }
+    let mut function = ir::lower(&check_file(source_path));
+    opt::optimize(&mut function, level, &load_limits(), |pass, function| {
+        if !dot && level > 0 {
+            println!("== {pass} ==");
+            println!("{function}");
+        }
+    });
+    if dot {
+        print!("{}", function.to_dot());
+    } else if level == 0 {
+        print!("{function}");
+    }
+}
+
+async fn serve() {
+    if !check_table(table::TABLE_PATH) {
+        eprintln!("Refusing to start with an invalid {}", table::TABLE_PATH);
+        std::process::exit(1);
+    }
+    let state = Arc::new(AppState {
+        table: table::ParseTable::load(table::TABLE_PATH).unwrap_or_else(|err| {
+            eprintln!("Table Error: {err}");
+            std::process::exit(1);
+        }),
+        tera: Tera::new("templates/**/*").unwrap(),
+        limits: load_limits(),
+    });
+    println!(
+        "INFO: loaded {} states and {} nonterminals from {}",
+        state.table.states.len(),
+        state.table.nonterminals.len(),
+        table::TABLE_PATH
+    );
+
     let app = Router::new()
         .route("/", get(index))
         .route("/tokens", post(generate_tokens))
         .route("/tab1", get(tab1))
         .route("/tab2", get(tab2))
         .route("/tab3", get(tab3))
+        .route("/tab4", get(tab4))
+        .route("/tab5", get(tab5))
         .route("/syntax", post(check_syntax))
-        .route("/compile", post(check_language));
+        .route("/compile", post(check_language))
+        .route("/run", post(run_code))
+        .route("/bytecode", post(show_bytecode))
+        .layer(AddExtensionLayer::new(state));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("Listening on -> http://{addr}");
@@ -141,6 +614,20 @@ async fn main() {
         .unwrap();
 }
 
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("gen-table") => generate_table(&args[1..]),
+        Some("compare-tables") => compare_tables(&args[1..]),
+        Some("validate-table") => validate_table(&args[1..]),
+        Some("bench-lexer") => bench_lexer(&args[1..]),
+        Some("run") => run_file(&args[1..]).await,
+        Some("ir") => show_ir(&args[1..]),
+        _ => serve().await,
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use crate::lexer::{tokenize_code, Token};
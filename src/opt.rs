@@ -0,0 +1,560 @@
+use crate::interpreter::{self, Limits, Value};
+use crate::ir::{BlockId, Function, Instr, Operand, Terminator, VarId};
+use crate::lexer::Span;
+use crate::ssa::{self, Dominators};
+use std::collections::{HashMap, HashSet};
+
+/**
+ Operands compared by their text, so 0.0 and -0.0 or 1 and 1.0 differ
+*/
+fn operand_key(operand: &Operand) -> String {
+    match operand {
+        Operand::Var(var) => format!("v{var}"),
+        Operand::Const(value) => format!("{value:?}"),
+    }
+}
+
+/**
+ Value of an instruction whose operands are all constants. Instructions that
+ fail, like a division by zero or a string past `limits`, are left for the
+ program to fail at run time.
+*/
+fn fold(instr: &Instr, limits: &Limits) -> Option<Value> {
+    let constant = |operand: &Operand| match operand {
+        Operand::Const(value) => Some(value.clone()),
+        Operand::Var(_) => None,
+    };
+    let value = match instr {
+        Instr::Unary { op, operand, .. } => {
+            interpreter::unary_operation(*op, constant(operand)?, Span::default()).ok()?
+        }
+        Instr::Binary {
+            op, left, right, ..
+        } => interpreter::binary_operation(*op, constant(left)?, constant(right)?, Span::default())
+            .ok()?,
+        _ => return None,
+    };
+    // a string past the limit must still fail when the program builds it
+    match &value {
+        Value::String(text) if text.len() > limits.max_string_length => None,
+        _ => Some(value),
+    }
+}
+
+/**
+ Constant folding and propagation, with copy propagation. Variables that are
+ copies, constants or phis of a single value are replaced by that value
+ everywhere; branches on constants become jumps and the blocks no longer
+ reached are dropped. Repeats until nothing changes. Folding keeps to the
+ string length of `limits`, the run must fail where the program would.
+*/
+pub fn propagate_constants(function: &mut Function, limits: &Limits) {
+    loop {
+        let mut replacements: HashMap<VarId, Operand> = HashMap::new();
+        for block in &function.blocks {
+            for instr in &block.instrs {
+                let Some(dest) = instr.dest() else {
+                    continue;
+                };
+                let replacement = match instr {
+                    Instr::Copy { src, .. } => Some(src.clone()),
+                    Instr::Phi { args, .. } => {
+                        // a phi only taking itself and one other value is that value
+                        let mut others = args
+                            .iter()
+                            .map(|(_, arg)| arg)
+                            .filter(|arg| **arg != Operand::Var(dest));
+                        match others.next() {
+                            Some(first)
+                                if others.all(|arg| operand_key(arg) == operand_key(first)) =>
+                            {
+                                Some(first.clone())
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => fold(instr, limits).map(Operand::Const),
+                };
+                if let Some(replacement) = replacement {
+                    replacements.insert(dest, replacement);
+                }
+            }
+        }
+
+        let mut changed = !replacements.is_empty();
+        function.substitute(&replacements);
+        for block in &mut function.blocks {
+            block.instrs.retain(|instr| {
+                !instr
+                    .dest()
+                    .is_some_and(|dest| replacements.contains_key(&dest))
+            });
+            if let Terminator::Branch {
+                condition: Operand::Const(Value::Boolean(condition)),
+                then_block,
+                else_block,
+            } = block.terminator
+            {
+                let target = if condition { then_block } else { else_block };
+                block.terminator = Terminator::Jump(target);
+                changed = true;
+            }
+        }
+        function.remove_unreachable();
+        if !changed {
+            break;
+        }
+    }
+}
+
+/**
+ Dead code elimination. Starting from prints, branch conditions and the
+ instructions that may fail, marks what they use and drops the rest, so
+ unused chains and cycles of phis go together.
+*/
+pub fn eliminate_dead_code(function: &mut Function) {
+    function.remove_unreachable();
+    let definitions = function.definitions();
+    let mut live: HashSet<VarId> = HashSet::new();
+    let mut worklist: Vec<VarId> = Vec::new();
+    let mut mark = |operand: &Operand, worklist: &mut Vec<VarId>| {
+        if let Operand::Var(var) = operand {
+            if live.insert(*var) {
+                worklist.push(*var);
+            }
+        }
+    };
+    for block in &function.blocks {
+        for instr in &block.instrs {
+            let root = matches!(instr, Instr::Print { .. }) || instr.may_fail();
+            if root {
+                if let Some(dest) = instr.dest() {
+                    mark(&Operand::Var(dest), &mut worklist);
+                }
+                for operand in instr.operands() {
+                    mark(operand, &mut worklist);
+                }
+            }
+        }
+        if let Some(operand) = block.terminator.operand() {
+            mark(operand, &mut worklist);
+        }
+    }
+    while let Some(var) = worklist.pop() {
+        if let Some(&(block, index)) = definitions.get(&var) {
+            let instr = function.blocks[block].instrs[index].clone();
+            for operand in instr.operands() {
+                mark(operand, &mut worklist);
+            }
+        }
+    }
+    for block in &mut function.blocks {
+        block.instrs.retain(|instr| match instr.dest() {
+            Some(dest) => live.contains(&dest),
+            None => true,
+        });
+    }
+}
+
+/**
+ Common subexpression elimination. Walks the dominator tree remembering the
+ operations computed so far, an operation already computed in a dominating
+ block is replaced by its result.
+*/
+pub fn eliminate_common_subexpressions(function: &mut Function) {
+    fn walk(
+        function: &mut Function,
+        dominators: &Dominators,
+        block: BlockId,
+        available: &mut Vec<HashMap<String, VarId>>,
+        replacements: &mut HashMap<VarId, Operand>,
+    ) {
+        let mut computed = HashMap::new();
+        let mut kept = Vec::new();
+        for mut instr in std::mem::take(&mut function.blocks[block].instrs) {
+            for operand in instr.operands_mut() {
+                while let Operand::Var(var) = operand {
+                    match replacements.get(var) {
+                        Some(replacement) => *operand = replacement.clone(),
+                        None => break,
+                    }
+                }
+            }
+            let key = match &instr {
+                Instr::Unary { op, operand, .. } => Some(format!("{op}{}", operand_key(operand))),
+                Instr::Binary {
+                    op, left, right, ..
+                } => Some(format!("{} {op} {}", operand_key(left), operand_key(right))),
+                _ => None,
+            };
+            if let (Some(key), Some(dest)) = (key, instr.dest()) {
+                let known = computed.get(&key).copied().or_else(|| {
+                    available
+                        .iter()
+                        .rev()
+                        .find_map(|scope| scope.get(&key).copied())
+                });
+                if let Some(known) = known {
+                    replacements.insert(dest, Operand::Var(known));
+                    continue;
+                }
+                computed.insert(key, dest);
+            }
+            kept.push(instr);
+        }
+        function.blocks[block].instrs = kept;
+        available.push(computed);
+        for &child in &dominators.children[block] {
+            walk(function, dominators, child, available, replacements);
+        }
+        available.pop();
+    }
+
+    let dominators = Dominators::new(function);
+    let mut replacements = HashMap::new();
+    walk(
+        function,
+        &dominators,
+        Function::ENTRY,
+        &mut Vec::new(),
+        &mut replacements,
+    );
+    // phis and terminators of blocks walked before the replaced definitions
+    function.substitute(&replacements);
+}
+
+/**
+ Loop-invariant code motion. Each `while` loop is found from its back edge,
+ instructions of its body whose operands do not change inside the loop move
+ to the block before its header. Instructions that may fail only move from
+ the header, which runs whenever the loop is entered, so hoisting never makes
+ a program fail that would not have. Inner loops go first.
+*/
+pub fn hoist_loop_invariants(function: &mut Function) {
+    let dominators = Dominators::new(function);
+    let predecessors = function.predecessors();
+
+    let mut loops: Vec<(BlockId, HashSet<BlockId>)> = Vec::new();
+    for (latch, block) in function.blocks.iter().enumerate() {
+        for header in block.successors() {
+            if !dominators.dominates(header, latch) {
+                continue;
+            }
+            // the blocks reaching the latch without going through the header
+            let mut body = HashSet::from([header]);
+            let mut worklist = vec![latch];
+            while let Some(block) = worklist.pop() {
+                if body.insert(block) {
+                    worklist.extend(predecessors[block].iter().copied());
+                }
+            }
+            loops.push((header, body));
+        }
+    }
+    loops.sort_by_key(|(_, body)| body.len());
+
+    for (header, body) in loops {
+        let outside: Vec<BlockId> = predecessors[header]
+            .iter()
+            .filter(|predecessor| !body.contains(predecessor))
+            .copied()
+            .collect();
+        // lowered loops are entered from a single block jumping to the header
+        let preheader = match outside[..] {
+            [preheader] if function.blocks[preheader].successors() == [header] => preheader,
+            _ => continue,
+        };
+
+        let mut defined_inside: HashSet<VarId> = HashSet::new();
+        for &block in &body {
+            defined_inside.extend(function.blocks[block].instrs.iter().filter_map(Instr::dest));
+        }
+        let mut blocks: Vec<BlockId> = body.iter().copied().collect();
+        blocks.sort_by_key(|block| (*block != header, *block));
+
+        let mut hoisted = Vec::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &blocks {
+                let mut failing_before = false;
+                let mut kept = Vec::new();
+                for instr in std::mem::take(&mut function.blocks[block].instrs) {
+                    let invariant = !matches!(instr, Instr::Phi { .. } | Instr::Print { .. })
+                        && instr.operands().iter().all(|operand| match operand {
+                            Operand::Var(var) => !defined_inside.contains(var),
+                            Operand::Const(_) => true,
+                        });
+                    let movable = !instr.may_fail() || (block == header && !failing_before);
+                    if invariant && movable {
+                        defined_inside.remove(&instr.dest().unwrap());
+                        hoisted.push(instr);
+                        changed = true;
+                    } else {
+                        failing_before |= instr.may_fail();
+                        kept.push(instr);
+                    }
+                }
+                function.blocks[block].instrs = kept;
+            }
+        }
+        function.blocks[preheader].instrs.extend(hoisted);
+    }
+}
+
+type Pass<'a> = &'a dyn Fn(&mut Function);
+
+/**
+ Optimization levels. `-O0` keeps the IR as lowered, `-O1` converts it to SSA
+ and folds constants and removes dead code, `-O2` also eliminates common
+ subexpressions and hoists loop invariants, cleaning up after them. `dump`
+ gets the function after each step with the name of the step. `limits` are
+ the ones the program will run under.
+*/
+pub fn optimize(
+    function: &mut Function,
+    level: u8,
+    limits: &Limits,
+    mut dump: impl FnMut(&str, &Function),
+) {
+    dump("lowered", function);
+    if level == 0 {
+        return;
+    }
+    ssa::to_ssa(function);
+    dump("ssa", function);
+    let propagate = |function: &mut Function| propagate_constants(function, limits);
+    let mut passes: Vec<(&str, Pass)> = vec![
+        ("constant propagation", &propagate),
+        ("dead code elimination", &eliminate_dead_code),
+    ];
+    if level >= 2 {
+        passes.extend([
+            (
+                "common subexpression elimination",
+                &eliminate_common_subexpressions as Pass,
+            ),
+            ("loop-invariant code motion", &hoist_loop_invariants),
+            // clean up what the moved and shared instructions left behind
+            ("constant propagation", &propagate),
+            ("dead code elimination", &eliminate_dead_code),
+        ]);
+    }
+    for (name, pass) in passes {
+        pass(function);
+        dump(name, function);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{self, Terminator};
+    use crate::test_support::{checked, corpus, corpus_code};
+    use std::sync::atomic::AtomicBool;
+
+    fn optimized(code: &str, level: u8) -> Function {
+        optimized_with(code, level, &Limits::default())
+    }
+
+    fn optimized_with(code: &str, level: u8, limits: &Limits) -> Function {
+        let mut function = ir::lower(&checked(code));
+        optimize(&mut function, level, limits, |_, _| {});
+        function
+    }
+
+    /// runs the IR, phis take the value of the block control came from
+    fn execute(function: &Function) -> (String, Option<String>) {
+        let mut values: Vec<Option<Value>> = vec![None; function.vars.len()];
+        let mut output = String::new();
+        let (mut block, mut previous) = (Function::ENTRY, Function::ENTRY);
+        loop {
+            let value = |values: &[Option<Value>], operand: &Operand| match operand {
+                Operand::Var(var) => values[*var].clone().expect("defined before used"),
+                Operand::Const(value) => value.clone(),
+            };
+            let phis: Vec<(VarId, Value)> = function.blocks[block]
+                .instrs
+                .iter()
+                .filter_map(|instr| match instr {
+                    Instr::Phi { dest, args } => {
+                        let (_, arg) = args.iter().find(|(from, _)| *from == previous)?;
+                        Some((*dest, value(&values, arg)))
+                    }
+                    _ => None,
+                })
+                .collect();
+            for (dest, phi) in phis {
+                values[dest] = Some(phi);
+            }
+            for instr in &function.blocks[block].instrs {
+                let result = match instr {
+                    Instr::Copy { src, .. } => Ok(value(&values, src)),
+                    Instr::Unary { op, operand, .. } => {
+                        interpreter::unary_operation(*op, value(&values, operand), Span::default())
+                    }
+                    Instr::Binary {
+                        op, left, right, ..
+                    } => interpreter::binary_operation(
+                        *op,
+                        value(&values, left),
+                        value(&values, right),
+                        Span::default(),
+                    ),
+                    Instr::Print { value: printed } => {
+                        output.push_str(&format!("{}\n", value(&values, printed)));
+                        continue;
+                    }
+                    Instr::Phi { .. } => continue,
+                };
+                match result {
+                    Ok(result) => values[instr.dest().unwrap()] = Some(result),
+                    Err(err) => return (output, Some(err.message)),
+                }
+            }
+            previous = block;
+            block = match &function.blocks[block].terminator {
+                Terminator::Jump(target) => *target,
+                Terminator::Branch {
+                    condition,
+                    then_block,
+                    else_block,
+                } => match value(&values, condition) {
+                    Value::Boolean(true) => *then_block,
+                    _ => *else_block,
+                },
+                Terminator::Return => return (output, None),
+            };
+        }
+    }
+
+    /// whether control can come back to the block, that is it is inside a loop
+    fn in_loop(function: &Function, block: BlockId) -> bool {
+        let mut seen = HashSet::new();
+        let mut worklist = function.blocks[block].successors();
+        while let Some(next) = worklist.pop() {
+            if next == block {
+                return true;
+            }
+            if seen.insert(next) {
+                worklist.extend(function.blocks[next].successors());
+            }
+        }
+        false
+    }
+
+    fn instrs(function: &Function) -> Vec<String> {
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instrs)
+            .map(|instr| function.instr_text(instr))
+            .collect()
+    }
+
+    #[test]
+    fn optimized_ir_runs_like_the_interpreter_on_the_corpus() {
+        let cancelled = AtomicBool::new(false);
+        for (name, code) in corpus() {
+            let interpreted = interpreter::run(&checked(&code), Limits::default(), &cancelled);
+            let expected = (interpreted.output, interpreted.error.map(|err| err.message));
+            for level in 0..=2 {
+                let function = optimized(&code, level);
+                assert_eq!(
+                    execute(&function),
+                    expected,
+                    "{name} at -O{level}:\n{function}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ssa_assigns_each_variable_once() {
+        let mut function = ir::lower(&checked(&corpus_code("control_flow")));
+        ssa::to_ssa(&mut function);
+        let predecessors = function.predecessors();
+        let mut assigned = HashSet::new();
+        for (id, block) in function.blocks.iter().enumerate() {
+            for instr in &block.instrs {
+                if let Some(dest) = instr.dest() {
+                    assert!(
+                        assigned.insert(dest),
+                        "{} assigned twice",
+                        function.vars[dest].name
+                    );
+                }
+                if let Instr::Phi { args, .. } = instr {
+                    assert_eq!(args.len(), predecessors[id].len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constants_fold_through_straight_line_code() {
+        let function = optimized(
+            "fn main() { a: integer; b: integer; a = 6; b = a * 7; print(b); }",
+            1,
+        );
+        assert_eq!(instrs(&function), ["print 42"]);
+    }
+
+    #[test]
+    fn failing_instructions_are_neither_folded_nor_removed() {
+        let function = optimized("fn main() { a: integer; a = 1 / 0; }", 2);
+        assert_eq!(instrs(&function), ["%t0#0 = 1 / 0"]);
+    }
+
+    #[test]
+    fn strings_past_the_configured_length_are_not_folded() {
+        let code = "fn main() { s: string; s = \"ab\" + \"cd\"; print(s); }";
+        let function = optimized(code, 1);
+        assert_eq!(instrs(&function), ["print \"abcd\""]);
+        let limits = Limits {
+            max_string_length: 3,
+            ..Limits::default()
+        };
+        let function = optimized_with(code, 1, &limits);
+        assert_eq!(
+            instrs(&function),
+            ["%t0#0 = \"ab\" + \"cd\"", "print %t0#0"]
+        );
+    }
+
+    #[test]
+    fn common_subexpressions_are_computed_once() {
+        let function = optimized(
+            "fn main() { a: integer; b: integer; while (a < 10) { b = a * 3 + a * 3; a = a + 1; }; print(b); }",
+            2,
+        );
+        let products = instrs(&function)
+            .iter()
+            .filter(|instr| instr.ends_with("* 3"))
+            .count();
+        assert_eq!(products, 1);
+    }
+
+    #[test]
+    fn loop_invariants_move_before_the_loop() {
+        let code = "fn main() {
+            i: integer; n: integer; flag: boolean;
+            while (n < 5) { n = n + 1; };
+            while (i < n * 2) { flag = n < 3; i = i + 1; };
+            print(flag);
+        }";
+        let function = optimized(code, 2);
+        for (id, block) in function.blocks.iter().enumerate() {
+            for instr in &block.instrs {
+                let text = function.instr_text(instr);
+                if text.ends_with("< 3") || text.ends_with("* 2") {
+                    assert!(
+                        !in_loop(&function, id),
+                        "{text} is still in a loop:\n{function}"
+                    );
+                }
+            }
+        }
+        assert_eq!(execute(&function).0, "false\n");
+    }
+}
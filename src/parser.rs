@@ -1,22 +1,13 @@
-use crate::lexer::Token;
-use serde_json::{Error, Value};
-use std::collections::HashMap;
-use std::thread;
-use std::time::Duration;
-
-fn read_json_file() -> Result<Value, Error> {
-    let file_path = "syntax_table.json";
-    let file_contents = std::fs::read_to_string(file_path).expect("Error reading file");
-    let json_value: serde_json::Value =
-        serde_json::from_str(&file_contents).expect("Error parsing JSON");
-    Ok(json_value)
-}
+use crate::lexer::{Span, Token, TokenKind};
+use crate::table::{Action, ParseTable, StateId};
+use crate::util::trace;
+use serde::Serialize;
+use std::fmt;
 
 fn print_syntax_tree(node: &SyntaxTreeNode, depth: usize) {
     let indent = " ".repeat(depth * 2);
 
-    // Print the current node's token value (replace with actual token information)
-    println!("{}({:#?})", indent, node.token.value); // Adjust this according to your Token struct
+    trace!("{}({:#?})", indent, node.symbol);
 
     // Recursively print each child
     for child in &node.children {
@@ -24,114 +15,248 @@ fn print_syntax_tree(node: &SyntaxTreeNode, depth: usize) {
     }
 }
 
-fn time_sleep(seconds: u64) {
-    let sleep_duration = Duration::from_secs(seconds);
-    thread::sleep(sleep_duration);
-}
-
+/**
+ Node of the parse tree. `symbol` is the token name for leaves and the
+ nonterminal name for interior nodes, only leaves carry a `token`. Interior
+ nodes span their children, empty productions get an empty span at the
+ lookahead token.
+*/
 #[derive(Debug)]
 pub struct SyntaxTreeNode {
+    pub symbol: String,
+    pub token: Option<Token>,
+    pub span: Span,
+    pub children: Vec<SyntaxTreeNode>,
+}
+
+impl SyntaxTreeNode {
+    fn leaf(token: &Token) -> SyntaxTreeNode {
+        SyntaxTreeNode {
+            symbol: token.value.name().to_string(),
+            token: Some(token.clone()),
+            span: token.span,
+            children: Vec::new(),
+        }
+    }
+
+    fn interior(symbol: &str, children: Vec<SyntaxTreeNode>, lookahead: &Token) -> SyntaxTreeNode {
+        // empty productions at the edges would stretch the span to the lookahead
+        let mut covered = children
+            .iter()
+            .filter(|child| child.span.start < child.span.end);
+        let span = match (covered.next(), covered.next_back()) {
+            (Some(first), Some(last)) => first.span.merge(last.span),
+            (Some(only), None) => only.span,
+            _ => lookahead.span.collapse_to_start(),
+        };
+        SyntaxTreeNode {
+            symbol: symbol.to_string(),
+            token: None,
+            span,
+            children,
+        }
+    }
+}
+
+/**
+ Token with no action in the current state, along with the tokens the state
+ would have accepted
+*/
+#[derive(Debug, Serialize)]
+pub struct SyntaxError {
     pub token: Token,
-    pub children: Vec<Box<SyntaxTreeNode>>,
+    pub state: StateId,
+    pub expected: Vec<TokenKind>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let found = if self.token.value == TokenKind::Eof {
+            "end of input".to_string()
+        } else {
+            format!("{} '{}'", self.token.value, self.token.lexeme)
+        };
+        let expected: Vec<&str> = self.expected.iter().map(|kind| kind.name()).collect();
+        write!(
+            f,
+            "unexpected {} at {}, expected one of: {}",
+            found,
+            self.token.span,
+            expected.join(", ")
+        )
+    }
 }
 
 /**
- SLR syntax analysis on tokens
+ Outcome of a parse. With recovery the tree can be partial: commands that
+ failed to parse become `command-nt` nodes holding an `error` node.
 */
-pub fn syntax_parse(tokens: Vec<Token>) -> (bool, Option<Vec<Box<SyntaxTreeNode>>>) {
-    println!("- iniciando analisador sintatico");
+#[derive(Debug)]
+pub struct ParseResult {
+    pub syntax_tree: Vec<SyntaxTreeNode>,
+    pub errors: Vec<SyntaxError>,
+}
 
-    let afd = read_json_file().unwrap();
-    let mut pilha: Vec<String> = Vec::new();
-    pilha.push("0".to_string()); // Convert the initial state to String
-    println!("- iniciou com estado 0");
+impl ParseResult {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
 
-    let mut node_stack: Vec<Box<SyntaxTreeNode>> = Vec::new();
+/// nonterminal the parser resumes from after an error
+const RECOVERY_NONTERMINAL: &str = "command-nt";
+
+fn is_sync_token(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Semicolon | TokenKind::RightCurlyBrace | TokenKind::Eof
+    )
+}
+
+/**
+ Panic-mode recovery: pops states until one has a GOTO on `command-nt`,
+ discards input up to the next `;` or `}` and pushes a `command-nt` node
+ wrapping what was thrown away. When the sync token is `}` a missing `;` is
+ assumed so the enclosing block can still close.
+ Returns the new input position, or None when no state can recover.
+*/
+fn recover(
+    tokens: &[Token],
+    mut i: usize,
+    table: &ParseTable,
+    pilha: &mut Vec<StateId>,
+    node_stack: &mut Vec<SyntaxTreeNode>,
+) -> Option<usize> {
+    let mut discarded: Vec<SyntaxTreeNode> = Vec::new();
+    let recovery_state = loop {
+        let top = *pilha.last()?;
+        if let Some(target) = table.goto(top, RECOVERY_NONTERMINAL) {
+            break target;
+        }
+        pilha.pop();
+        if let Some(node) = node_stack.pop() {
+            discarded.insert(0, node);
+        }
+    };
+
+    while !is_sync_token(tokens[i].value) {
+        discarded.push(SyntaxTreeNode::leaf(&tokens[i]));
+        i += 1;
+    }
+    trace!(
+        "  -- Recovering in state {recovery_state}, resuming at {}",
+        tokens[i].value
+    );
+
+    let error_node = SyntaxTreeNode::interior("error", discarded, &tokens[i]);
+    node_stack.push(SyntaxTreeNode::interior(
+        RECOVERY_NONTERMINAL,
+        vec![error_node],
+        &tokens[i],
+    ));
+    pilha.push(recovery_state);
+
+    if tokens[i].value == TokenKind::RightCurlyBrace {
+        if let Some(Action::Shift(target)) = table.action(recovery_state, TokenKind::Semicolon) {
+            let semicolon =
+                Token::new(TokenKind::Semicolon, "", tokens[i].span.collapse_to_start());
+            node_stack.push(SyntaxTreeNode::leaf(&semicolon));
+            pilha.push(*target);
+        }
+    }
+    Some(i)
+}
+
+/**
+ SLR syntax analysis on tokens, recovering from errors to report all of them
+*/
+pub fn syntax_parse(tokens: Vec<Token>, table: &ParseTable) -> ParseResult {
+    trace!("- iniciando analisador sintatico");
+
+    // ERROR tokens are reported by the lexer, the grammar never sees them
+    let tokens: Vec<Token> = tokens
+        .into_iter()
+        .filter(|token| token.value != TokenKind::Error)
+        .collect();
+
+    let mut pilha: Vec<usize> = vec![0];
+    trace!("- iniciou com estado 0");
+
+    let mut node_stack: Vec<SyntaxTreeNode> = Vec::new();
+    let mut errors: Vec<SyntaxError> = Vec::new();
+    let mut last_recovery: Option<usize> = None;
 
     let mut i = 0;
     while i < tokens.len() {
-        let token_value = &tokens[i].value;
-
-        println!(" - Token: {}", token_value);
-        if let Some(action) = afd
-            .get(pilha.last().expect("Failed to get last element from pilha"))
-            .and_then(|entry| {
-                entry["ACTION"]
-                    .get(token_value)
-                    .or(entry["ACTION"].get("ANY"))
-            })
-        {
-            let move_parts: Vec<&str> = action
-                .as_str()
-                .expect("Failed to convert action to a string")
-                .split(' ')
-                .collect();
-            println!(" - Action: {}", action);
-
-            match move_parts[0] {
-                "S" => {
-                    let new_node = Box::new(SyntaxTreeNode {
-                        token: tokens[i].clone(),
-                        children: Vec::new(),
-                    });
-                    node_stack.push(new_node);
+        let token_value = tokens[i].value;
+
+        trace!(" - Token: {}", token_value);
+        let state = *pilha.last().expect("Failed to get last element from pilha");
+        if let Some(action) = table.action(state, token_value) {
+            trace!(" - Action: {}", action);
+
+            match action {
+                Action::Shift(shift_value) => {
+                    node_stack.push(SyntaxTreeNode::leaf(&tokens[i]));
                     // Shift - Push and advance the pointer
-                    println!("  -- Shift operation");
-                    let shift_value = move_parts[1].to_string();
-                    pilha.push(shift_value);
+                    trace!("  -- Shift operation");
+                    pilha.push(*shift_value);
                     i += 1;
                 }
-                "R" => {
+                Action::Reduce {
+                    len: reduce_count,
+                    lhs: nonterminal,
+                } => {
                     // Reduce - Pop and Redirect (to indicate reduction)
-                    println!("  -- Reduce operation");
-                    let reduce_count = move_parts[1]
-                        .parse::<usize>()
-                        .expect("Failed to parse move_parts[1] into usize");
-
+                    trace!("  -- Reduce operation");
+                    // ParseTable::from_json checked that the stack is deep enough and has the GOTO
                     pilha.truncate(pilha.len() - reduce_count);
-                    let pilha_last = pilha.last().expect("Failed to get last element from pilha");
-                    let goto_value = if move_parts.len() > 2 {
-                        &afd[pilha_last]["GOTO"][move_parts[2]]
-                    } else {
-                        panic!("move_parts does not have enough elements to access move_parts[2]");
-                    };
-                    if goto_value.get(token_value).is_some() {
-                        // Handle the case when the first key is not "ANY"
-                        let desvio = goto_value[token_value].to_string();
-                        pilha.push(desvio);
-                    } else if goto_value.get("ANY").is_some() {
-                        // Handle the "ANY" case
-                        let desvio = goto_value["ANY"].to_string();
-                        pilha.push(desvio);
-                    }
+                    let pilha_last = *pilha.last().expect("Failed to get last element from pilha");
+                    let desvio = table
+                        .goto(pilha_last, nonterminal)
+                        .expect("reduces are checked when the table is loaded");
+                    pilha.push(desvio);
                     //
-                    let mut children = Vec::new();
-                    for _ in 0..reduce_count {
-                        children.push(node_stack.pop().unwrap());
-                    }
-                    children.reverse(); // Ensure the children are in the correct order
-                    let new_node = Box::new(SyntaxTreeNode {
-                        token: Token::new(move_parts[2], "", 0, 0, 0),
-                        children,
-                    });
+                    let children = node_stack.split_off(node_stack.len() - reduce_count);
+                    let new_node = SyntaxTreeNode::interior(nonterminal, children, &tokens[i]);
                     node_stack.push(new_node);
                 }
-                "ACC" => {
-                    println!("- \x1b[32mOk\x1b[0m Accept operation: Parsing successful");
-                    println!("- \x1b[32mSyntax Tree produced:\x1b[0m");
-                    print_syntax_tree(&node_stack.get(0).unwrap(), 0);
-                    return (true, Some(node_stack));
-                }
-                _ => {
-                    println!("- Error: Invalid action");
-                    return (false, None);
+                Action::Accept => {
+                    trace!("- \x1b[32mOk\x1b[0m Accept operation: Parsing successful");
+                    trace!("- \x1b[32mSyntax Tree produced:\x1b[0m");
+                    print_syntax_tree(node_stack.first().unwrap(), 0);
+                    break;
                 }
             }
-            println!("- Current stack: {:?}", pilha);
-            println!(" ")
+            trace!("- Current stack: {:?}", pilha);
+            trace!(" ")
         } else {
-            return (false, None);
+            if last_recovery == Some(i) {
+                // failing again on the token we resumed at, drop it to make progress
+                if token_value == TokenKind::Eof {
+                    break;
+                }
+                i += 1;
+            } else {
+                trace!("- Error: no action for {} in state {}", token_value, state);
+                errors.push(SyntaxError {
+                    token: tokens[i].clone(),
+                    state,
+                    expected: table.expected(state),
+                });
+            }
+            match recover(&tokens, i, table, &mut pilha, &mut node_stack) {
+                Some(resume_at) => {
+                    i = resume_at;
+                    last_recovery = Some(i);
+                }
+                None => break,
+            }
         }
     }
-    (false, None)
+
+    ParseResult {
+        syntax_tree: node_stack,
+        errors,
+    }
 }
@@ -1,229 +1,258 @@
-use crate::lexer::Token;
-use crate::parser::SyntaxTreeNode;
+use crate::ast::{BinaryOp, Expr, Ident, Program, Stmt, Type, UnaryOp};
+use crate::lexer::Span;
+use crate::util::trace;
 use std::collections::HashMap;
 
-// fn get_nested_child<'a>(node: &'a SyntaxTreeNode, levels: usize) -> Option<&'a SyntaxTreeNode> {
-//     let mut current_node = node;
-//     for _ in 0..levels {
-//         match current_node.children.get(0) {
-//             Some(child) => current_node = child,
-//             None => return None,
-//         }
-//     }
-//     Some(current_node)
-// }
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum TokenType {
-    String,
-    Integer,
-    // ... other token types ...
-}
-
 #[derive(Debug)]
 pub struct SemanticError {
     pub message: String,
-    pub line: i32,
-    pub initial_position: i32,
-    pub final_position: i32,
+    pub span: Span,
 }
 
-fn find_terminal_nodes_with_terms(
-    node: &SyntaxTreeNode,
-    terminal_tokens: &mut Vec<Token>,
-    include_terms: &Vec<String>,
-    exclude_terms: &Vec<String>,
-) {
-    for child in &node.children {
-        if exclude_terms
-            .iter()
-            .any(|term| child.token.value.contains(term))
-        {
-            continue;
-        } else if include_terms
-            .iter()
-            .any(|term| child.token.value.contains(term))
-        {
-            find_terminal_nodes_with_terms(child, terminal_tokens, include_terms, exclude_terms);
-        } else {
-            terminal_tokens.push(child.token.clone());
-        }
+fn lookup(scopes: &[HashMap<String, Type>], name: &Ident) -> Result<Type, SemanticError> {
+    scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.get(&name.name))
+        .copied()
+        .ok_or_else(|| SemanticError {
+            message: format!("Variable {} is not declared", name.name),
+            span: name.span,
+        })
+}
+
+/**
+ Typing rules of the binary operators, None when the operands don't fit.
+ There are no implicit conversions: both sides always have the same type.
+*/
+fn binary_type(op: BinaryOp, left: Type, right: Type) -> Option<Type> {
+    if left != right {
+        return None;
+    }
+    match (op, left) {
+        (BinaryOp::Add, Type::Integer | Type::Float | Type::String) => Some(left),
+        (
+            BinaryOp::Subtract
+            | BinaryOp::Multiply
+            | BinaryOp::Divide
+            | BinaryOp::Modulus
+            | BinaryOp::Exponent,
+            Type::Integer | Type::Float,
+        ) => Some(left),
+        (
+            BinaryOp::Greater | BinaryOp::Less | BinaryOp::GreaterEqual | BinaryOp::LessEqual,
+            Type::Integer | Type::Float | Type::String,
+        ) => Some(Type::Boolean),
+        (BinaryOp::Equal | BinaryOp::NotEqual, _) => Some(Type::Boolean),
+        (BinaryOp::And | BinaryOp::Or, Type::Boolean) => Some(Type::Boolean),
+        _ => None,
     }
 }
 
-fn check_types_exp(exp_node: &SyntaxTreeNode) -> Result<TokenType, SemanticError> {
-    // check if types are all the same, and if variable is the same type
-    let mut terminal_tokens: Vec<Token> = Vec::new();
-    let include_terms: Vec<String> = vec![
-        "exp1-nt".to_string(),
-        "exp2-nt".to_string(),
-        "exp-nt".to_string(),
-    ];
-    let exclude_terms: Vec<String> = vec![
-        "operator-nt".to_string(),
-        "EQUAL".to_string(),
-        "variable-nt".to_string(),
-    ];
-    find_terminal_nodes_with_terms(
-        exp_node,
-        &mut terminal_tokens,
-        &include_terms,
-        &exclude_terms,
-    );
-    let first_token_value = terminal_tokens[0].value.clone();
-    let is_all_the_same: bool = terminal_tokens
-        .iter()
-        .all(|token| token.value == first_token_value);
-
-    if is_all_the_same {
-        match terminal_tokens.last() {
-            Some(token) if token.value == "NUMBER" => return Ok(TokenType::Integer),
-            Some(token) if token.value == "STRING" => return Ok(TokenType::String),
-            Some(_) => panic!("Type does not Exist"),
-            None => panic!("Declaration Type"),
-        }
-    } else {
-        return Err(SemanticError {
-            message: "Missing right-hand side of assignment".to_string(),
-            line: exp_node.token.line,
-            initial_position: exp_node.token.initial_position,
-            final_position: exp_node.token.final_position,
-        });
+fn unary_type(op: UnaryOp, operand: Type) -> Option<Type> {
+    match (op, operand) {
+        (UnaryOp::Negate, Type::Integer | Type::Float) => Some(operand),
+        (UnaryOp::Not, Type::Boolean) => Some(Type::Boolean),
+        _ => None,
     }
 }
 
-fn parse_declare(
-    node: &SyntaxTreeNode,
-    scopes: &mut Vec<HashMap<String, TokenType>>,
-    scope_pointer: &mut i32,
-) -> Result<(), SemanticError> {
-    let mut terminal_tokens: Vec<Token> = Vec::new();
-    let include_terms: Vec<String> = vec![
-        "exp1-nt".to_string(),
-        "exp2-nt".to_string(),
-        "variable-nt".to_string(),
-    ];
-    let exclude_terms: Vec<String> = vec!["operator-nt".to_string()];
-    find_terminal_nodes_with_terms(node, &mut terminal_tokens, &include_terms, &exclude_terms);
-    let new_variable = &terminal_tokens.first().expect("New declared variable");
-
-    let is_declared = scopes[scope_pointer.clone() as usize].contains_key(&new_variable.lexeme);
-    if !is_declared {
-        match terminal_tokens.last() {
-            Some(token) if token.value == "INTEGER_TYPE" => {
-                scopes[scope_pointer.clone() as usize]
-                    .insert(new_variable.lexeme.clone(), TokenType::Integer);
-            }
-            Some(token) if token.value == "STRING_TYPE" => {
-                scopes[scope_pointer.clone() as usize]
-                    .insert(new_variable.lexeme.clone(), TokenType::String);
-            }
-            Some(_) => panic!("Type does not Exist"),
-            None => panic!("Declaration Type"),
+/**
+ Type of an expression, computed bottom-up from its literals and variables
+*/
+fn check_expr(expr: &Expr, scopes: &[HashMap<String, Type>]) -> Result<Type, SemanticError> {
+    match expr {
+        Expr::Literal { value, .. } => Ok(value.ty()),
+        Expr::Var(name) => lookup(scopes, name),
+        Expr::Unary { op, operand, span } => {
+            let operand_type = check_expr(operand, scopes)?;
+            unary_type(*op, operand_type).ok_or_else(|| SemanticError {
+                message: format!("Operator {op} cannot be applied to {operand_type}"),
+                span: *span,
+            })
+        }
+        Expr::Binary {
+            op,
+            left,
+            right,
+            span,
+        } => {
+            let left_type = check_expr(left, scopes)?;
+            let right_type = check_expr(right, scopes)?;
+            binary_type(*op, left_type, right_type).ok_or_else(|| SemanticError {
+                message: format!("Operator {op} cannot be applied to {left_type} and {right_type}"),
+                span: *span,
+            })
         }
-    } else {
-        return Err(SemanticError {
-            message: format!("Variable {} already declared", new_variable.lexeme).to_string(),
-            line: new_variable.line,
-            initial_position: new_variable.initial_position,
-            final_position: new_variable.final_position,
-        });
     }
-
-    Ok(())
 }
 
-fn parse_assign(
-    node: &SyntaxTreeNode,
-    scopes: &mut Vec<HashMap<String, TokenType>>,
-    scope_pointer: &mut i32,
+fn check_condition(
+    keyword: &str,
+    condition: &Expr,
+    scopes: &[HashMap<String, Type>],
 ) -> Result<(), SemanticError> {
-    let mut terminal_tokens: Vec<Token> = Vec::new();
-    let include_terms: Vec<String> = vec![
-        "exp1-nt".to_string(),
-        "exp2-nt".to_string(),
-        "variable-nt".to_string(),
-    ];
-    let exclude_terms: Vec<String> = vec!["operator-nt".to_string()];
-    find_terminal_nodes_with_terms(node, &mut terminal_tokens, &include_terms, &exclude_terms);
-    let variable = &terminal_tokens.first().expect("expects a Variable");
-
-    let is_declared = scopes
-        .iter()
-        .any(|scope| scope.contains_key(&variable.lexeme));
-    if !is_declared {
+    let condition_type = check_expr(condition, scopes)?;
+    if condition_type != Type::Boolean {
         return Err(SemanticError {
-            message: format!("Variable {} not assigned", variable.lexeme).to_string(),
-            line: variable.line,
-            initial_position: variable.initial_position,
-            final_position: variable.final_position,
+            message: format!("Condition of {keyword} must be boolean, found {condition_type}"),
+            span: condition.span(),
         });
     }
-
-    let inferred_type = check_types_exp(node).expect("expects Inferred Type");
-    let variable_type = scopes[scope_pointer.clone() as usize]
-        .get(&variable.lexeme)
-        .expect("expects variable Type");
-    if variable_type != &inferred_type {
-        return Err(SemanticError {
-            message: format!(
-                "Variable {} assigned wrong type {:?}",
-                variable.lexeme, inferred_type
-            )
-            .to_string(),
-            line: variable.line,
-            initial_position: variable.initial_position,
-            final_position: variable.final_position,
-        });
-    }
-
     Ok(())
 }
 
-fn traverse(
-    node: &SyntaxTreeNode,
-    scopes: &mut Vec<HashMap<String, TokenType>>,
-    scope_pointer: &mut i32,
-) -> Result<(), SemanticError> {
-    match &node.token.value[..] {
-        "assign-nt" => {
-            println!("semantic assign-nt: {:?}", &node.token);
-            parse_assign(node, scopes, scope_pointer)?;
+fn check_stmt(stmt: &Stmt, scopes: &mut Vec<HashMap<String, Type>>) -> Result<(), SemanticError> {
+    match stmt {
+        Stmt::Declare { name, ty, .. } => {
+            trace!("semantic declare: {}", name.name);
+            let scope = scopes.last_mut().expect("expects a scope");
+            if scope.contains_key(&name.name) {
+                return Err(SemanticError {
+                    message: format!("Variable {} already declared", name.name),
+                    span: name.span,
+                });
+            }
+            scope.insert(name.name.clone(), *ty);
         }
-        "declare-nt" => {
-            println!("semantic declare-nt: {:?}", &node.token);
-            parse_declare(node, scopes, scope_pointer)?;
+        Stmt::Assign { name, value, .. } => {
+            trace!("semantic assign: {}", name.name);
+            let variable_type = lookup(scopes, name)?;
+            let value_type = check_expr(value, scopes)?;
+            if variable_type != value_type {
+                return Err(SemanticError {
+                    message: format!(
+                        "Cannot assign {} to variable {} of type {}",
+                        value_type, name.name, variable_type
+                    ),
+                    span: value.span(),
+                });
+            }
         }
-        "LEFT_CURLY_BRACE" => {
-            *scope_pointer += 1;
-            scopes.push(HashMap::new());
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            // every branch is a block with its own scope
+            check_condition("if", condition, scopes)?;
+            check_stmt(then_branch, scopes)?;
+            if let Some(else_branch) = else_branch {
+                check_stmt(else_branch, scopes)?;
+            }
         }
-        "RIGHT_CURLY_BRACE" => {
-            scopes.remove(scope_pointer.clone() as usize);
-            *scope_pointer -= 1
+        Stmt::While {
+            condition, body, ..
+        } => {
+            check_condition("while", condition, scopes)?;
+            check_stmt(body, scopes)?;
         }
-        "program-nt" | "command-nt" | "command-list-nt" => {
-            for child in &node.children {
-                traverse(child, scopes, scope_pointer)?;
-            }
+        Stmt::Print { value, .. } => {
+            check_expr(value, scopes)?;
+        }
+        Stmt::Block { body, .. } => {
+            scopes.push(HashMap::new());
+            let checked = body.iter().try_for_each(|stmt| check_stmt(stmt, scopes));
+            scopes.pop();
+            checked?;
         }
-        _ => {}
     }
+    Ok(())
+}
 
+pub fn semantic_analysis(program: &Program) -> Result<(), SemanticError> {
+    trace!("Starting semantic analysis");
+
+    let mut scopes: Vec<HashMap<String, Type>> = vec![HashMap::new()];
+    for stmt in &program.body {
+        check_stmt(stmt, &mut scopes)?;
+    }
+
+    trace!("Semantic analysis completed.");
     Ok(())
 }
 
-pub fn semantic_analysis(syntax_tree: &Vec<Box<SyntaxTreeNode>>) -> Result<(), SemanticError> {
-    println!("Starting semantic analysis");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::lowered;
 
-    let mut scope_pointer = 0;
-    let mut scopes: Vec<HashMap<String, TokenType>> = Vec::new();
-    scopes.push(HashMap::new());
+    /// message and line of the first semantic error of a program
+    fn check(code: &str) -> Result<(), (String, u32)> {
+        let program = lowered(code).unwrap_or_else(|errors| panic!("{code}: {errors:?}"));
+        semantic_analysis(&program).map_err(|err| (err.message, err.span.start_line))
+    }
 
-    for node in syntax_tree {
-        traverse(node, &mut scopes, &mut scope_pointer)?;
+    fn error(code: &str) -> (String, u32) {
+        check(code).expect_err("expects a semantic error")
     }
 
-    println!("Semantic analysis completed.");
-    Ok(())
+    #[test]
+    fn well_typed_program_passes() {
+        let code = "fn main() {
+            x: integer; s: string; b: boolean;
+            x = 1 + 2 * 3; s = \"a\" + \"b\"; b = x > 2 && s != \"c\";
+            while (b) { x = x - 1; b = x > 0; };
+        }";
+        assert_eq!(check(code), Ok(()));
+    }
+
+    #[test]
+    fn operand_types_must_match() {
+        let code = "fn main() {\n  x: integer;\n  x = 1 + \"a\";\n}";
+        assert_eq!(
+            error(code),
+            (
+                "Operator + cannot be applied to integer and string".to_string(),
+                3
+            )
+        );
+        let code = "fn main() {\n  b: boolean;\n  b = !1;\n}";
+        assert_eq!(
+            error(code),
+            ("Operator ! cannot be applied to integer".to_string(), 3)
+        );
+    }
+
+    #[test]
+    fn conditions_must_be_boolean() {
+        let code = "fn main() {\n  x: integer;\n  if (x) { x = 1; };\n}";
+        assert_eq!(
+            error(code),
+            (
+                "Condition of if must be boolean, found integer".to_string(),
+                3
+            )
+        );
+        let code = "fn main() {\n  s: string;\n\n  while (s + \"a\") { s = \"b\"; };\n}";
+        assert_eq!(
+            error(code),
+            (
+                "Condition of while must be boolean, found string".to_string(),
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn variables_must_be_declared_in_scope() {
+        let code = "fn main() {\n  x = 1;\n}";
+        assert_eq!(error(code), ("Variable x is not declared".to_string(), 2));
+        // a block's declarations end with it
+        let code = "fn main() {\n  if (true) { y: integer; };\n  y = 2;\n}";
+        assert_eq!(error(code), ("Variable y is not declared".to_string(), 3));
+    }
+
+    #[test]
+    fn assigned_value_must_have_the_variable_type() {
+        let code = "fn main() {\n  x: integer;\n  x = 1.5;\n}";
+        assert_eq!(
+            error(code),
+            (
+                "Cannot assign float to variable x of type integer".to_string(),
+                3
+            )
+        );
+    }
 }
@@ -0,0 +1,307 @@
+use crate::ir::{BlockId, Function, Instr, Operand, VarId};
+use std::collections::HashSet;
+
+/**
+ Dominator tree of a function's control-flow graph, computed with the
+ iterative algorithm of Cooper, Harvey and Kennedy. A block dominates another
+ when every path from the entry to the second goes through the first.
+*/
+pub struct Dominators {
+    /// immediate dominator of each block, None for the entry and unreachable blocks
+    pub idom: Vec<Option<BlockId>>,
+    pub children: Vec<Vec<BlockId>>,
+    /// blocks where the dominance of each block ends
+    pub frontier: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(function: &Function) -> Dominators {
+        let count = function.blocks.len();
+        let order = function.reverse_postorder();
+        let mut position = vec![usize::MAX; count];
+        for (index, block) in order.iter().enumerate() {
+            position[*block] = index;
+        }
+        let predecessors = function.predecessors();
+
+        let entry = Function::ENTRY;
+        let mut idom: Vec<Option<BlockId>> = vec![None; count];
+        idom[entry] = Some(entry);
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while position[a] > position[b] {
+                    a = idom[a].unwrap();
+                }
+                while position[b] > position[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let new_idom = predecessors[block]
+                    .iter()
+                    .filter(|predecessor| idom[**predecessor].is_some())
+                    .copied()
+                    .reduce(|a, b| intersect(&idom, a, b));
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[entry] = None;
+
+        let mut children = vec![Vec::new(); count];
+        for &block in &order {
+            if let Some(parent) = idom[block] {
+                children[parent].push(block);
+            }
+        }
+
+        let mut frontier: Vec<Vec<BlockId>> = vec![Vec::new(); count];
+        for &block in &order {
+            let reachable: Vec<BlockId> = predecessors[block]
+                .iter()
+                .filter(|predecessor| position[**predecessor] != usize::MAX)
+                .copied()
+                .collect();
+            if reachable.len() < 2 {
+                continue;
+            }
+            for predecessor in reachable {
+                let mut runner = predecessor;
+                while Some(runner) != idom[block] {
+                    if !frontier[runner].contains(&block) {
+                        frontier[runner].push(block);
+                    }
+                    match idom[runner] {
+                        Some(parent) => runner = parent,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Dominators {
+            idom,
+            children,
+            frontier,
+        }
+    }
+
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        let mut current = Some(block);
+        while let Some(id) = current {
+            if id == dominator {
+                return true;
+            }
+            current = self.idom[id];
+        }
+        false
+    }
+}
+
+/**
+ Variables live on entry to each block, before SSA: used in the block before
+ being assigned or live on entry to a successor and not assigned in the block
+*/
+fn live_in(function: &Function) -> Vec<HashSet<VarId>> {
+    let count = function.blocks.len();
+    let mut uses: Vec<HashSet<VarId>> = vec![HashSet::new(); count];
+    let mut defs: Vec<HashSet<VarId>> = vec![HashSet::new(); count];
+    for (id, block) in function.blocks.iter().enumerate() {
+        let operands = block.instrs.iter().flat_map(|instr| {
+            let dest = instr.dest();
+            instr
+                .operands()
+                .into_iter()
+                .map(move |operand| (operand, dest))
+        });
+        for (operand, dest) in operands {
+            if let Operand::Var(var) = operand {
+                if !defs[id].contains(var) {
+                    uses[id].insert(*var);
+                }
+            }
+            if let Some(dest) = dest {
+                defs[id].insert(dest);
+            }
+        }
+        if let Some(Operand::Var(var)) = block.terminator.operand() {
+            if !defs[id].contains(var) {
+                uses[id].insert(*var);
+            }
+        }
+    }
+
+    let mut live = uses.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..count).rev() {
+            for successor in function.blocks[id].successors() {
+                let incoming: Vec<VarId> = live[successor]
+                    .iter()
+                    .filter(|var| !defs[id].contains(var))
+                    .copied()
+                    .collect();
+                for var in incoming {
+                    changed |= live[id].insert(var);
+                }
+            }
+        }
+    }
+    live
+}
+
+struct Renamer<'a> {
+    function: &'a mut Function,
+    dominators: &'a Dominators,
+    /// variable each phi of a block was inserted for, in order
+    phi_vars: Vec<Vec<VarId>>,
+    /// current version of each variable of the original function
+    stacks: Vec<Vec<VarId>>,
+    versions: Vec<usize>,
+}
+
+impl Renamer<'_> {
+    fn new_version(&mut self, var: VarId) -> VarId {
+        let name = format!("{}#{}", self.function.vars[var].name, self.versions[var]);
+        self.versions[var] += 1;
+        let temporary = self.function.vars[var].temporary;
+        let version = self.function.add_var(name, temporary);
+        self.stacks[var].push(version);
+        version
+    }
+
+    fn current(&self, var: VarId) -> VarId {
+        *self.stacks[var]
+            .last()
+            .expect("a definition reaches every use of a checked program")
+    }
+
+    fn rename(&mut self, block: BlockId) {
+        let mut defined = Vec::new();
+        let phis = self.phi_vars[block].len();
+        for index in 0..self.function.blocks[block].instrs.len() {
+            let original = if index < phis {
+                Some(self.phi_vars[block][index])
+            } else {
+                let operands: Vec<Operand> = self.function.blocks[block].instrs[index]
+                    .operands()
+                    .into_iter()
+                    .map(|operand| match operand {
+                        Operand::Var(var) => Operand::Var(self.current(*var)),
+                        constant => constant.clone(),
+                    })
+                    .collect();
+                let instr = &mut self.function.blocks[block].instrs[index];
+                for (slot, operand) in instr.operands_mut().into_iter().zip(operands) {
+                    *slot = operand;
+                }
+                instr.dest()
+            };
+            if let Some(original) = original {
+                let version = self.new_version(original);
+                let instr = &mut self.function.blocks[block].instrs[index];
+                *instr.dest_mut().unwrap() = version;
+                defined.push(original);
+            }
+        }
+        if let Some(Operand::Var(var)) = self.function.blocks[block].terminator.operand() {
+            let current = self.current(*var);
+            *self.function.blocks[block]
+                .terminator
+                .operand_mut()
+                .unwrap() = Operand::Var(current);
+        }
+
+        for successor in self.function.blocks[block].successors() {
+            for index in 0..self.phi_vars[successor].len() {
+                let var = self.phi_vars[successor][index];
+                let current = self.stacks[var].last().copied();
+                if let Instr::Phi { args, .. } = &mut self.function.blocks[successor].instrs[index]
+                {
+                    for (predecessor, arg) in args.iter_mut() {
+                        if *predecessor == block {
+                            let current =
+                                current.expect("a definition reaches every live variable");
+                            *arg = Operand::Var(current);
+                        }
+                    }
+                }
+            }
+        }
+
+        for child in self.dominators.children[block].clone() {
+            self.rename(child);
+        }
+        for var in defined {
+            self.stacks[var].pop();
+        }
+    }
+}
+
+/**
+ Converts a function into SSA form: each variable is assigned once, a phi
+ joins the versions reaching a block from its predecessors. Phis are only
+ placed where their variable is live, so every argument has a definition.
+ Versions are named after their variable, `x#0`, `x#1`, ...
+*/
+pub fn to_ssa(function: &mut Function) {
+    function.remove_unreachable();
+    let dominators = Dominators::new(function);
+    let live = live_in(function);
+    let predecessors = function.predecessors();
+    let original_count = function.vars.len();
+
+    let mut def_blocks: Vec<HashSet<BlockId>> = vec![HashSet::new(); original_count];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for instr in &block.instrs {
+            if let Some(dest) = instr.dest() {
+                def_blocks[dest].insert(id);
+            }
+        }
+    }
+
+    let mut phi_vars: Vec<Vec<VarId>> = vec![Vec::new(); function.blocks.len()];
+    for (var, blocks) in def_blocks.iter().enumerate() {
+        let mut worklist: Vec<BlockId> = blocks.iter().copied().collect();
+        let mut has_phi: HashSet<BlockId> = HashSet::new();
+        while let Some(block) = worklist.pop() {
+            for &frontier in &dominators.frontier[block] {
+                if has_phi.contains(&frontier) || !live[frontier].contains(&var) {
+                    continue;
+                }
+                has_phi.insert(frontier);
+                phi_vars[frontier].push(var);
+                if !blocks.contains(&frontier) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+    for (block, vars) in phi_vars.iter().enumerate() {
+        let phis = vars.iter().map(|var| Instr::Phi {
+            dest: *var,
+            args: predecessors[block]
+                .iter()
+                .map(|predecessor| (*predecessor, Operand::Var(*var)))
+                .collect(),
+        });
+        function.blocks[block].instrs.splice(0..0, phis);
+    }
+
+    let mut renamer = Renamer {
+        function,
+        dominators: &dominators,
+        phi_vars,
+        stacks: vec![Vec::new(); original_count],
+        versions: vec![0; original_count],
+    };
+    renamer.rename(Function::ENTRY);
+}
@@ -0,0 +1,518 @@
+use crate::lexer::{self, TokenKind};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+pub const TABLE_PATH: &str = "syntax_table.json";
+
+#[derive(Debug)]
+pub struct TableError {
+    pub state: String,
+    pub message: String,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "state {}: {}", self.state, self.message)
+    }
+}
+
+pub fn read_table(path: &str) -> Result<Value, String> {
+    let file_contents =
+        std::fs::read_to_string(path).map_err(|err| format!("Error reading {path}: {err}"))?;
+    serde_json::from_str(&file_contents).map_err(|err| format!("Error parsing {path}: {err}"))
+}
+
+/**
+ GOTO targets of a nonterminal entry, either a bare state or an object keyed by
+ lookahead token (or `ANY`)
+*/
+fn goto_targets(entry: &Value) -> Vec<(Option<&str>, &Value)> {
+    match entry {
+        Value::Object(by_token) => by_token
+            .iter()
+            .map(|(token, target)| (Some(token.as_str()), target))
+            .collect(),
+        other => vec![(None, other)],
+    }
+}
+
+/**
+ Checks a syntax table against the lexer's token set: every ACTION terminal is
+ a token the lexer emits (or `$`/`ANY`), every action is well formed, every
+ reduce names a nonterminal, every target state exists and every state is
+ reachable from state 0.
+*/
+pub fn validate_table(table: &Value, token_names: &[String]) -> Vec<TableError> {
+    let mut errors = Vec::new();
+    let states = match table.as_object() {
+        Some(states) => states,
+        None => {
+            errors.push(TableError {
+                state: "-".to_string(),
+                message: "table is not a JSON object of states".to_string(),
+            });
+            return errors;
+        }
+    };
+    if !states.contains_key("0") {
+        errors.push(TableError {
+            state: "0".to_string(),
+            message: "initial state 0 is missing".to_string(),
+        });
+    }
+
+    let is_terminal = |name: &str| name == "ANY" || token_names.iter().any(|t| t == name);
+    let nonterminals: HashSet<&str> = states
+        .values()
+        .filter_map(|entry| entry["GOTO"].as_object())
+        .flat_map(|gotos| gotos.keys().map(|k| k.as_str()))
+        .collect();
+    let state_exists = |target: &Value| -> Option<String> {
+        let target = match target {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+            _ => return None,
+        };
+        states.contains_key(&target).then_some(target)
+    };
+
+    let mut edges: Vec<(String, String)> = Vec::new();
+    for (state, entry) in states {
+        let error = |message: String| TableError {
+            state: state.clone(),
+            message,
+        };
+
+        let actions = match entry["ACTION"].as_object() {
+            Some(actions) => actions,
+            None => {
+                errors.push(error("ACTION is missing or not an object".to_string()));
+                continue;
+            }
+        };
+        for (terminal, action) in actions {
+            if !is_terminal(terminal) {
+                errors.push(error(format!(
+                    "ACTION on '{terminal}' which the lexer never produces"
+                )));
+            }
+            let action = match action.as_str() {
+                Some(action) => action,
+                None => {
+                    errors.push(error(format!("action on '{terminal}' is not a string")));
+                    continue;
+                }
+            };
+            let parts: Vec<&str> = action.split(' ').collect();
+            match parts[..] {
+                ["S", target] => match state_exists(&Value::String(target.to_string())) {
+                    Some(target) => edges.push((state.clone(), target)),
+                    None => errors.push(error(format!(
+                        "'{action}' on '{terminal}' shifts to a missing state"
+                    ))),
+                },
+                ["R", count, nonterminal] => {
+                    if count.parse::<usize>().is_err() {
+                        errors.push(error(format!(
+                            "'{action}' on '{terminal}' has an invalid length"
+                        )));
+                    }
+                    if !nonterminals.contains(nonterminal) || is_terminal(nonterminal) {
+                        errors.push(error(format!(
+                            "'{action}' on '{terminal}' reduces to '{nonterminal}' which is not a nonterminal"
+                        )));
+                    }
+                }
+                ["R", _] => errors.push(error(format!(
+                    "'{action}' on '{terminal}' does not name a nonterminal"
+                ))),
+                ["ACC"] => {}
+                _ => errors.push(error(format!(
+                    "'{action}' on '{terminal}' is not a valid action"
+                ))),
+            }
+        }
+
+        let gotos = match entry["GOTO"].as_object() {
+            Some(gotos) => gotos,
+            None => {
+                errors.push(error("GOTO is missing or not an object".to_string()));
+                continue;
+            }
+        };
+        for (nonterminal, targets) in gotos {
+            for (token, target) in goto_targets(targets) {
+                if let Some(token) = token {
+                    if !is_terminal(token) {
+                        errors.push(error(format!(
+                            "GOTO {nonterminal} on '{token}' which the lexer never produces"
+                        )));
+                    }
+                }
+                match state_exists(target) {
+                    Some(target) => edges.push((state.clone(), target)),
+                    None => errors.push(error(format!(
+                        "GOTO {nonterminal} targets missing state {target}"
+                    ))),
+                }
+            }
+        }
+    }
+
+    let mut reachable: BTreeSet<&str> = BTreeSet::new();
+    let mut pending: VecDeque<&str> = VecDeque::from(["0"]);
+    while let Some(state) = pending.pop_front() {
+        if !reachable.insert(state) {
+            continue;
+        }
+        for (from, to) in &edges {
+            if from == state {
+                pending.push_back(to);
+            }
+        }
+    }
+    for state in states.keys() {
+        if !reachable.contains(state.as_str()) {
+            errors.push(TableError {
+                state: state.clone(),
+                message: "unreachable from state 0".to_string(),
+            });
+        }
+    }
+
+    errors
+}
+
+pub type StateId = usize;
+
+/**
+ Parser action, deserialized from the `"S 2"`, `"R 3 program-nt"` and `"ACC"`
+ strings of the table
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Action {
+    Shift(StateId),
+    Reduce { len: usize, lhs: String },
+    Accept,
+}
+
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(action: String) -> Result<Action, String> {
+        let parts: Vec<&str> = action.split(' ').collect();
+        match parts[..] {
+            ["S", target] => target
+                .parse()
+                .map(Action::Shift)
+                .map_err(|_| format!("invalid shift target in '{action}'")),
+            ["R", len, lhs] => match len.parse() {
+                Ok(len) => Ok(Action::Reduce {
+                    len,
+                    lhs: lhs.to_string(),
+                }),
+                Err(_) => Err(format!("invalid reduce length in '{action}'")),
+            },
+            ["R", _] => Err(format!("reduce '{action}' does not name a nonterminal")),
+            ["ACC"] => Ok(Action::Accept),
+            _ => Err(format!("invalid action '{action}'")),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Shift(target) => write!(f, "S {}", target),
+            Action::Reduce { len, lhs } => write!(f, "R {} {}", len, lhs),
+            Action::Accept => write!(f, "ACC"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawGoto {
+    State(StateId),
+    ByToken(BTreeMap<String, StateId>),
+}
+
+#[derive(Deserialize)]
+struct RawState {
+    #[serde(rename = "ACTION")]
+    action: BTreeMap<String, Action>,
+    #[serde(rename = "GOTO")]
+    goto: BTreeMap<String, RawGoto>,
+}
+
+/**
+ One row of the compiled table. Actions are indexed by `TokenKind` and gotos
+ by nonterminal id; `default_action` is the `ANY` entry of the row.
+*/
+#[derive(Debug)]
+pub struct State {
+    pub actions: Vec<Option<Action>>,
+    pub default_action: Option<Action>,
+    pub gotos: Vec<Option<StateId>>,
+}
+
+/**
+ Syntax table compiled once into dense rows so the parser never touches JSON
+*/
+#[derive(Debug)]
+pub struct ParseTable {
+    pub nonterminals: Vec<String>,
+    nonterminal_ids: HashMap<String, usize>,
+    pub states: Vec<State>,
+    /// tokens the lexer hands to the parser, what an `ANY` entry stands for
+    terminals: Vec<TokenKind>,
+}
+
+impl ParseTable {
+    pub fn load(path: &str) -> Result<ParseTable, String> {
+        let file_contents =
+            std::fs::read_to_string(path).map_err(|err| format!("Error reading {path}: {err}"))?;
+        ParseTable::from_json(&file_contents).map_err(|err| format!("{path}: {err}"))
+    }
+
+    /**
+     Deserializes and compiles a table, failing on the first malformed entry
+    */
+    pub fn from_json(json: &str) -> Result<ParseTable, String> {
+        let raw: HashMap<String, RawState> =
+            serde_json::from_str(json).map_err(|err| err.to_string())?;
+
+        let mut nonterminals: Vec<String> = Vec::new();
+        for entry in raw.values() {
+            for nonterminal in entry.goto.keys() {
+                if !nonterminals.contains(nonterminal) {
+                    nonterminals.push(nonterminal.clone());
+                }
+            }
+        }
+        nonterminals.sort();
+        let nonterminal_ids: HashMap<String, usize> = nonterminals
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (name.clone(), id))
+            .collect();
+
+        let mut rows: Vec<State> = Vec::new();
+        for index in 0..raw.len() {
+            let entry = raw.get(&index.to_string()).ok_or(format!(
+                "state {index} is missing, states must be numbered 0..{}",
+                raw.len()
+            ))?;
+
+            let mut row = State {
+                actions: vec![None; TokenKind::ALL.len()],
+                default_action: None,
+                gotos: vec![None; nonterminals.len()],
+            };
+            for (terminal, action) in &entry.action {
+                match action {
+                    Action::Shift(target) if *target >= raw.len() => {
+                        return Err(format!(
+                            "state {index}: '{action}' on '{terminal}' shifts to a missing state"
+                        ))
+                    }
+                    Action::Reduce { lhs, .. } if !nonterminal_ids.contains_key(lhs) => {
+                        return Err(format!(
+                            "state {index}: '{action}' on '{terminal}' reduces to unknown nonterminal '{lhs}'"
+                        ))
+                    }
+                    _ => {}
+                }
+                if terminal == "ANY" {
+                    row.default_action = Some(action.clone());
+                    continue;
+                }
+                match TokenKind::from_name(terminal) {
+                    Some(kind) => row.actions[kind as usize] = Some(action.clone()),
+                    None => {
+                        return Err(format!(
+                            "state {index}: ACTION on '{terminal}' which is not a token"
+                        ))
+                    }
+                }
+            }
+            for (nonterminal, goto) in &entry.goto {
+                // GOTO only depends on the nonterminal, lookahead keys are a legacy shape
+                let targets: BTreeSet<StateId> = match goto {
+                    RawGoto::State(target) => BTreeSet::from([*target]),
+                    RawGoto::ByToken(by_token) => by_token.values().cloned().collect(),
+                };
+                if targets.len() != 1 {
+                    return Err(format!(
+                        "state {index}: GOTO {nonterminal} must have exactly one target state"
+                    ));
+                }
+                let target = *targets.iter().next().unwrap();
+                if target >= raw.len() {
+                    return Err(format!(
+                        "state {index}: GOTO {nonterminal} targets missing state {target}"
+                    ));
+                }
+                row.gotos[nonterminal_ids[nonterminal]] = Some(target);
+            }
+            rows.push(row);
+        }
+
+        let token_names = lexer::token_names();
+        let terminals = TokenKind::ALL
+            .iter()
+            .filter(|kind| token_names.iter().any(|name| name == kind.name()))
+            .cloned()
+            .collect();
+        let table = ParseTable {
+            nonterminals,
+            nonterminal_ids,
+            states: rows,
+            terminals,
+        };
+        table.check_reductions()?;
+        Ok(table)
+    }
+
+    /**
+     Checks that every reduce can run on any stack the table builds: going back
+     `len` transitions from the reducing state never passes the initial state,
+     and every state it can uncover has a GOTO on the reduced nonterminal
+    */
+    fn check_reductions(&self) -> Result<(), String> {
+        let mut predecessors: Vec<BTreeSet<StateId>> = vec![BTreeSet::new(); self.states.len()];
+        for (state, row) in self.states.iter().enumerate() {
+            let shifts = row
+                .actions
+                .iter()
+                .chain([&row.default_action])
+                .filter_map(|action| match action {
+                    Some(Action::Shift(target)) => Some(*target),
+                    _ => None,
+                });
+            for target in shifts.chain(row.gotos.iter().flatten().copied()) {
+                predecessors[target].insert(state);
+            }
+        }
+
+        for (state, row) in self.states.iter().enumerate() {
+            for action in row.actions.iter().chain([&row.default_action]).flatten() {
+                let Action::Reduce { len, lhs } = action else {
+                    continue;
+                };
+                let mut uncovered = BTreeSet::from([state]);
+                for _ in 0..*len {
+                    if uncovered.contains(&0) {
+                        return Err(format!(
+                            "state {state}: '{action}' can pop the initial state"
+                        ));
+                    }
+                    uncovered = uncovered
+                        .iter()
+                        .flat_map(|below| predecessors[*below].iter().copied())
+                        .collect();
+                }
+                if let Some(missing) = uncovered
+                    .iter()
+                    .find(|below| self.goto(**below, lhs).is_none())
+                {
+                    return Err(format!(
+                        "state {state}: '{action}' can uncover state {missing} which has no GOTO {lhs}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     Action of a state on a token, falling back to the row's `ANY` entry
+    */
+    pub fn action(&self, state: StateId, kind: TokenKind) -> Option<&Action> {
+        let row = self.states.get(state)?;
+        row.actions[kind as usize]
+            .as_ref()
+            .or(row.default_action.as_ref())
+    }
+
+    /**
+     Tokens that have an action in a state, every token the parser can receive
+     when the row has an `ANY` entry
+    */
+    pub fn expected(&self, state: StateId) -> Vec<TokenKind> {
+        let row = match self.states.get(state) {
+            Some(row) => row,
+            None => return Vec::new(),
+        };
+        self.terminals
+            .iter()
+            .filter(|kind| row.default_action.is_some() || row.actions[**kind as usize].is_some())
+            .cloned()
+            .collect()
+    }
+
+    pub fn goto(&self, state: StateId, nonterminal: &str) -> Option<StateId> {
+        let id = self.nonterminal_ids.get(nonterminal)?;
+        self.states.get(state)?.gotos[*id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_table_loads() {
+        let table = ParseTable::load(TABLE_PATH).unwrap();
+        assert_eq!(table.goto(0, "program-nt"), Some(1));
+    }
+
+    #[test]
+    fn any_entry_expects_only_tokens_the_parser_receives() {
+        let json = r#"{
+            "0": { "ACTION": { "ANY": "S 1" }, "GOTO": {} },
+            "1": { "ACTION": { "$": "ACC" }, "GOTO": {} }
+        }"#;
+        let table = ParseTable::from_json(json).unwrap();
+        let expected = table.expected(0);
+        assert!(expected.contains(&TokenKind::Variable));
+        assert!(expected.contains(&TokenKind::Eof));
+        for kind in [
+            TokenKind::Whitespace,
+            TokenKind::LineComment,
+            TokenKind::BlockComment,
+            TokenKind::Error,
+        ] {
+            assert!(!expected.contains(&kind), "{kind} is never parsed");
+        }
+        assert_eq!(table.expected(1), [TokenKind::Eof]);
+    }
+
+    #[test]
+    fn reduce_deeper_than_the_stack_is_rejected() {
+        let json = r#"{
+            "0": { "ACTION": { "VARIABLE": "S 1" }, "GOTO": { "exp-nt": 2 } },
+            "1": { "ACTION": { "ANY": "R 2 exp-nt" }, "GOTO": {} },
+            "2": { "ACTION": { "$": "ACC" }, "GOTO": {} }
+        }"#;
+        let err = ParseTable::from_json(json).unwrap_err();
+        assert_eq!(err, "state 1: 'R 2 exp-nt' can pop the initial state");
+    }
+
+    #[test]
+    fn reduce_uncovering_a_state_without_goto_is_rejected() {
+        let json = r#"{
+            "0": { "ACTION": { "VARIABLE": "S 1" }, "GOTO": { "exp-nt": 2 } },
+            "1": { "ACTION": { "$": "R 1 variable-nt" }, "GOTO": {} },
+            "2": { "ACTION": { "$": "ACC" }, "GOTO": { "variable-nt": 2 } }
+        }"#;
+        let err = ParseTable::from_json(json).unwrap_err();
+        assert_eq!(
+            err,
+            "state 1: 'R 1 variable-nt' can uncover state 0 which has no GOTO variable-nt"
+        );
+    }
+}
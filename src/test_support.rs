@@ -0,0 +1,60 @@
+use crate::ast::{self, Program};
+use crate::lexer::scan;
+use crate::parser::syntax_parse;
+use crate::table::{ParseTable, TABLE_PATH};
+use std::sync::OnceLock;
+
+pub const CORPUS_PATH: &str = "corpus";
+
+/**
+ The generated syntax table, loaded once for every test
+*/
+pub fn table() -> &'static ParseTable {
+    static TABLE: OnceLock<ParseTable> = OnceLock::new();
+    TABLE.get_or_init(|| ParseTable::load(TABLE_PATH).unwrap_or_else(|err| panic!("{err}")))
+}
+
+/**
+ AST of a program before semantic analysis, or its syntax or lowering errors
+*/
+pub fn lowered(code: &str) -> Result<Program, Vec<String>> {
+    let result = syntax_parse(scan(code), table());
+    if !result.is_ok() {
+        return Err(result.errors.iter().map(ToString::to_string).collect());
+    }
+    ast::lower(&result.syntax_tree).map_err(|err| vec![err.message])
+}
+
+/**
+ AST of a program that passes every check, panics on any error
+*/
+pub fn checked(code: &str) -> Program {
+    crate::check_program(scan(code), table())
+        .unwrap_or_else(|errors| panic!("{code}\ndoes not check: {errors:?}"))
+}
+
+/**
+ Source of the corpus program `name`, without its `.txt` extension
+*/
+pub fn corpus_code(name: &str) -> String {
+    std::fs::read_to_string(format!("{CORPUS_PATH}/{name}.txt")).unwrap()
+}
+
+/**
+ Every corpus program with its path and source, sorted by path
+*/
+pub fn corpus() -> Vec<(String, String)> {
+    let mut paths: Vec<_> = std::fs::read_dir(CORPUS_PATH)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "{CORPUS_PATH} has no programs");
+    paths
+        .into_iter()
+        .map(|path| {
+            let code = std::fs::read_to_string(&path).unwrap();
+            (path.display().to_string(), code)
+        })
+        .collect()
+}
@@ -1,4 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static TRACE: AtomicBool = AtomicBool::new(true);
+
 pub fn read_file(path: &str) -> String {
-    let file_contents = std::fs::read_to_string(path).expect("Error reading file");
-    file_contents
+    std::fs::read_to_string(path).expect("Error reading file")
 }
+
+/**
+ Turns the step by step logs of the parser and the semantic analysis on or off,
+ the server keeps them and the `run` command hides them behind the program output
+*/
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn trace_enabled() -> bool {
+    TRACE.load(Ordering::Relaxed)
+}
+
+/// `println!` that only prints while tracing is enabled
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::util::trace_enabled() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use trace;
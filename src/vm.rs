@@ -0,0 +1,199 @@
+use crate::ast::UnaryOp;
+use crate::codegen::{Chunk, Instruction};
+use crate::interpreter::{self, Budget, ErrorKind, Execution, Limits, RuntimeError, Value};
+use crate::lexer::Span;
+use std::sync::atomic::AtomicBool;
+
+/**
+ Stack machine running a chunk. Slots are empty until their declaration
+ stores the default value, the code never loads an empty slot.
+*/
+struct Vm<'a> {
+    chunk: &'a Chunk,
+    stack: Vec<Value>,
+    slots: Vec<Option<Value>>,
+    output: String,
+    budget: Budget<'a>,
+}
+
+impl Vm<'_> {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("expects a value on the stack")
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        let mut pc = 0;
+        while let Some(&instruction) = self.chunk.code.get(pc) {
+            let span = self.chunk.spans[pc];
+            for step in &self.chunk.steps[pc] {
+                self.budget.step(*step)?;
+            }
+            pc += 1;
+            match instruction {
+                Instruction::Const(index) => self.stack.push(self.chunk.constants[index].clone()),
+                Instruction::Load(slot) => {
+                    let value = self.slots[slot].clone().expect("slot stored before loaded");
+                    self.stack.push(value);
+                }
+                Instruction::Store(slot) => {
+                    let value = self.pop();
+                    let removed = self.slots[slot].as_ref().map_or(0, Value::size);
+                    self.budget.allocate(value.size(), removed, span)?;
+                    self.slots[slot] = Some(value);
+                }
+                Instruction::Negate | Instruction::Not => {
+                    let op = if instruction == Instruction::Negate {
+                        UnaryOp::Negate
+                    } else {
+                        UnaryOp::Not
+                    };
+                    let operand = self.pop();
+                    let result = interpreter::unary_operation(op, operand, span)?;
+                    self.stack.push(result);
+                }
+                Instruction::Jump(target) => pc = target,
+                Instruction::JumpIfFalse(target) | Instruction::JumpIfTrue(target) => {
+                    let jump_on = matches!(instruction, Instruction::JumpIfTrue(_));
+                    match self.pop() {
+                        Value::Boolean(value) if value == jump_on => pc = target,
+                        Value::Boolean(_) => {}
+                        value => return Err(not_boolean(value, span)),
+                    }
+                }
+                Instruction::Print => {
+                    let line = format!("{}\n", self.pop());
+                    self.budget.allocate(line.len(), 0, span)?;
+                    self.output.push_str(&line);
+                }
+                Instruction::Free(slot) => {
+                    if let Some(value) = self.slots[slot].take() {
+                        self.budget.free(value.size());
+                    }
+                }
+                _ => {
+                    let op = instruction
+                        .binary_op()
+                        .expect("the other instructions apply an operator");
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = interpreter::binary_operation(op, left, right, span)?;
+                    self.budget.check_string(&result, span)?;
+                    self.stack.push(result);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn not_boolean(value: Value, span: Span) -> RuntimeError {
+    RuntimeError {
+        kind: ErrorKind::Internal,
+        message: format!("Condition must be boolean, found {value:?}"),
+        span,
+    }
+}
+
+/**
+ Runs a compiled program under the same limits and with the same errors as
+ the tree interpreter. Stops with an error once `cancelled` is set.
+*/
+pub fn run(chunk: &Chunk, limits: Limits, cancelled: &AtomicBool) -> Execution {
+    let mut vm = Vm {
+        chunk,
+        stack: Vec::new(),
+        slots: vec![None; chunk.slots.len()],
+        output: String::new(),
+        budget: Budget::new(limits, cancelled),
+    };
+    let error = vm.execute().err();
+    Execution {
+        output: vm.output,
+        error,
+    }
+}
+
+pub async fn run_with_timeout(chunk: Chunk, limits: Limits) -> Execution {
+    interpreter::with_timeout(limits.timeout, move |cancelled| {
+        run(&chunk, limits, cancelled)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Program;
+    use crate::codegen::compile;
+    use crate::test_support::{checked, corpus, corpus_code};
+
+    /// output, then kind, message and span of the error, for comparing runs
+    fn summary(execution: &Execution) -> String {
+        let error = execution
+            .error
+            .as_ref()
+            .map(|err| format!("{:?}: {} at {}", err.kind, err.message, err.span));
+        format!("{}{error:?}", execution.output)
+    }
+
+    fn assert_same_run(name: &str, program: &Program, limits: Limits, cancelled: &AtomicBool) {
+        let interpreted = interpreter::run(program, limits, cancelled);
+        let executed = run(&compile(program), limits, cancelled);
+        assert_eq!(
+            summary(&interpreted),
+            summary(&executed),
+            "{name} runs differently on the VM with {limits:?}"
+        );
+    }
+
+    #[test]
+    fn vm_and_interpreter_agree_on_the_corpus() {
+        let cancelled = AtomicBool::new(false);
+        for (name, code) in corpus() {
+            assert_same_run(&name, &checked(&code), Limits::default(), &cancelled);
+        }
+    }
+
+    #[test]
+    fn vm_and_interpreter_stop_at_the_same_place_on_every_limit() {
+        let cancelled = AtomicBool::new(false);
+        for (name, code) in corpus() {
+            let program = checked(&code);
+            for max_steps in 0..400 {
+                let limits = Limits {
+                    max_steps,
+                    ..Limits::default()
+                };
+                assert_same_run(&name, &program, limits, &cancelled);
+            }
+            for max_string_length in 0..40 {
+                let limits = Limits {
+                    max_string_length,
+                    ..Limits::default()
+                };
+                assert_same_run(&name, &program, limits, &cancelled);
+            }
+            for max_memory in (0..600).step_by(4) {
+                let limits = Limits {
+                    max_memory,
+                    ..Limits::default()
+                };
+                assert_same_run(&name, &program, limits, &cancelled);
+            }
+            // an expired timeout stops both at their first step
+            let timed_out = AtomicBool::new(true);
+            assert_same_run(&name, &program, Limits::default(), &timed_out);
+        }
+    }
+
+    #[test]
+    fn arithmetic_errors_stop_the_vm() {
+        let cancelled = AtomicBool::new(false);
+        for name in ["division_by_zero", "overflow", "negative_exponent"] {
+            let program = checked(&corpus_code(name));
+            let executed = run(&compile(&program), Limits::default(), &cancelled);
+            let error = executed.error.expect("expects a runtime error");
+            assert_eq!(error.kind, ErrorKind::Arithmetic, "{name}");
+        }
+    }
+}